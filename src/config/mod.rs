@@ -1,10 +1,54 @@
 use std::fs;
+use crate::sort::SortKey;
 
 pub mod screen;
 
 #[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
 pub struct Config {
     pub nerd_fonts: bool,
+    /// Listen for remote commands on a socket exported as `$RX_SOCKET`
+    pub ipc: bool,
+    /// How many directories deep the recursive listing goes, unlimited if unset
+    pub recursive_max_depth: Option<usize>,
+    /// Globs of paths left out of the recursive listing, on top of the
+    /// `.gitignore` rules
+    pub ignore: Vec<String>,
+    /// Initial sort order of the listings
+    pub sort: SortKey,
+    pub sort_reverse: bool,
+    pub dirs_first: bool,
+    /// Show dotfiles in the listings
+    pub show_hidden: bool,
+    /// Leave out the paths ignored by `.gitignore` and `.ignore` files
+    pub hide_ignored: bool,
+    /// Show the git status of the entries inside repositories
+    pub git_status: bool,
+    /// Rename and move tracked files with `git mv`
+    pub git_mv: bool,
+    /// Segments of the status bar, on its left and right sides
+    pub status_left: Vec<String>,
+    pub status_right: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            nerd_fonts: true,
+            ipc: true,
+            recursive_max_depth: None,
+            ignore: vec![".git".to_string()],
+            sort: SortKey::Natural,
+            sort_reverse: false,
+            dirs_first: true,
+            show_hidden: false,
+            hide_ignored: false,
+            git_status: true,
+            git_mv: false,
+            status_left: ["path", "entries", "selection", "filter"].map(String::from).to_vec(),
+            status_right: ["info", "git", "hidden", "sort", "free"].map(String::from).to_vec(),
+        }
+    }
 }
 
 pub fn get_config() -> Config {
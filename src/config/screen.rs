@@ -19,9 +19,7 @@ impl ConfigScreen {
             subtitles,
             index: 0,
             current_selection: true,
-            config: Config{
-                nerd_fonts: true,
-            },
+            config: Config::default(),
         }
     }
 
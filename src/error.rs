@@ -1,5 +1,6 @@
 use std::fmt;
 use std::io;
+use std::path::PathBuf;
 
 #[derive(Debug)]
 pub enum ExplorerError {
@@ -9,6 +10,7 @@ pub enum ExplorerError {
     LuaError(mlua::Error),
     Other(String),
     NoLuaScript(),
+    InvalidPath(PathBuf),
 }
 
 impl fmt::Display for ExplorerError {
@@ -20,6 +22,7 @@ impl fmt::Display for ExplorerError {
             ExplorerError::LuaError(err)          => write!(f, "Lua error: {}", err),
             ExplorerError::Other(msg)             => write!(f, "{}", msg),
             ExplorerError::NoLuaScript()      => write!(f, "No Lua script found"),
+            ExplorerError::InvalidPath(path)      => write!(f, "No such file or directory: {}", path.display()),
         }
     }
 }
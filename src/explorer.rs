@@ -1,22 +1,46 @@
+use std::collections::HashMap;
 use std::io::{self, Write, BufWriter, IsTerminal};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs::File;
+use std::rc::Rc;
+use std::time::Duration;
 use crossterm::{terminal, execute};
-use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
+use crossterm::event::{DisableMouseCapture, EnableMouseCapture, Event, MouseEvent, MouseEventKind};
 use crossterm::style::Color;
 use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen, SetTitle};
 use mlua::Lua;
 
 use crate::config::Config;
 use crate::error::{Result, ExplorerError};
+use crate::file_ops;
 use crate::input::InputHandler;
-use crate::state::AppState;
-use crate::ui::Renderer;
-use crate::theme::Theme;
-use crate::lua::{create_rx_module, default_display_modules, DisplayModuleFn, Entry};
+use crate::ipc::{IpcServer, Request, Response};
+use crate::modes::ExplorerAction;
+use crate::sort::SortKey;
+use crate::state::{AppState, Listing};
+use crate::ui::{MillerColumns, Renderer, View};
+use crate::styled::StyledText;
+use crate::theme::{GitColors, Theme};
+use crate::lua::{create_rx_module, default_column_modules, default_display_modules, Align, Column, ColumnSort, DisplayModuleFn, Entry, FilterFn, LineContext, LineFn, ListingHooks, SortValue, SorterFn};
+
+/// Everything configured by the user's `init.lua`.
+pub struct Plugins {
+    pub lua: Lua,
+    pub display_modules: Vec<Column>,
+    /// Reduced set of modules for the side columns of the columns view
+    pub column_modules: Vec<Column>,
+    pub hooks: ListingHooks,
+    pub theme: Theme,
+}
 
 pub struct FileExplorer {
-    state: AppState,
+    tabs: Vec<AppState>,
+    active_tab: usize,
+    /// Tab shown in the inactive pane in dual-pane mode
+    other_pane: Option<usize>,
+    /// Parent and child listings around the active tab in the columns view
+    columns: Option<MillerColumns>,
+    column_modules: Rc<Vec<Column>>,
     renderer: Renderer,
     lua: Lua,
     is_tty_mode: bool,
@@ -24,39 +48,63 @@ pub struct FileExplorer {
 }
 
 impl FileExplorer {
-    pub fn new(config: Config) -> Result<Self> {
-        let mut display_modules = vec![];
-        let mut theme = Theme::default();
-        let lua = match Self::init_lua() {
-            Ok(lua) => {
-                display_modules = Self::setup_display_modules(&lua)?;
-                theme = Self::get_theme(&lua)?;
-                Ok(lua)
-            }
-            Err(e) => {
-                match e {
-                    ExplorerError::NoLuaScript() => {
-                        display_modules = default_display_modules(config.nerd_fonts);
-                        Ok(Lua::new())
-                    }
-                    _ => {
-                        eprintln!("Error initializing Lua: {}", e);
-                        Err(e)
-                    }
-                }
-            }
-        }?;
-        
-        
+    pub fn new(config: Config, start_path: &Path, selected: Option<&Path>, listing: Listing) -> Result<Self> {
+        let Plugins { lua, display_modules, column_modules, hooks, theme } = Self::load_plugins(&config)?;
+
+        std::env::set_current_dir(start_path)?;
+        let renderer = Renderer::new(theme);
+        let mut state = AppState::new(config, display_modules, hooks, start_path, listing)?;
+        state.viewport.resize(renderer.list_height());
+        if let Some(path) = selected {
+            state.select_path(path);
+        }
+
         Ok(Self {
-            state: AppState::new(config, display_modules)?,
-            renderer: Renderer::new(theme),
+            tabs: vec![state],
+            active_tab: 0,
+            other_pane: None,
+            columns: None,
+            column_modules: Rc::new(column_modules),
+            renderer,
             lua,
-            is_tty_mode: !std::io::stdout().is_terminal(),
+            // Keep the UI off stdout when it is captured, and off stdin when
+            // paths are piped in, by talking to the terminal directly
+            is_tty_mode: !std::io::stdout().is_terminal() || !std::io::stdin().is_terminal(),
             dirty: true,
         })
     }
 
+    /// Loads the user's `init.lua`, falling back to the default display
+    /// modules and theme when there is none.
+    pub fn load_plugins(config: &Config) -> Result<Plugins> {
+        match Self::init_lua() {
+            Ok(lua) => {
+                let mut display_modules = Self::setup_display_modules(&lua, "modules")?;
+                if display_modules.is_empty() {
+                    display_modules = default_display_modules(config.nerd_fonts);
+                }
+                let mut column_modules = Self::setup_display_modules(&lua, "column_modules")?;
+                if column_modules.is_empty() {
+                    column_modules = default_column_modules(config.nerd_fonts);
+                }
+                let hooks = Self::setup_hooks(&lua)?;
+                let theme = Self::get_theme(&lua)?;
+                Ok(Plugins { lua, display_modules, column_modules, hooks, theme })
+            }
+            Err(ExplorerError::NoLuaScript()) => Ok(Plugins {
+                lua: Lua::new(),
+                display_modules: default_display_modules(config.nerd_fonts),
+                column_modules: default_column_modules(config.nerd_fonts),
+                hooks: ListingHooks::default(),
+                theme: Theme::default(),
+            }),
+            Err(e) => {
+                eprintln!("Error initializing Lua: {}", e);
+                Err(e)
+            }
+        }
+    }
+
     fn init_lua() -> Result<Lua> {
         let lua = Lua::new();
         let config_dir = dirs::config_dir().unwrap().join("rx").join("lua");
@@ -115,27 +163,139 @@ impl FileExplorer {
         Ok(lua)
     }
 
-    fn setup_display_modules(lua: &Lua) -> Result<Vec<DisplayModuleFn>> {
+    /// Wraps the sorter and filter set with `rx.setSorter` and `rx.setFilter`.
+    fn setup_hooks(lua: &Lua) -> Result<ListingHooks> {
+        let rx_table: mlua::Table = lua.globals()
+            .get("rx")
+            .map_err(ExplorerError::LuaError)?;
+        let sorter: Option<mlua::Function> = rx_table.get("sorter").map_err(ExplorerError::LuaError)?;
+        let filter: Option<mlua::Function> = rx_table.get("filter").map_err(ExplorerError::LuaError)?;
+
+        let lua_clone = lua.clone();
+        let sorter = sorter.map(|func| -> SorterFn {
+            Box::new(move |a: &Entry, b: &Entry| {
+                let a = lua_clone.create_userdata(a.clone())?;
+                let b = lua_clone.create_userdata(b.clone())?;
+                func.call::<bool>((a, b))
+            })
+        });
+        let lua_clone = lua.clone();
+        let filter = filter.map(|func| -> FilterFn {
+            Box::new(move |entry: &Entry| {
+                let ud = lua_clone.create_userdata(entry.clone())?;
+                func.call::<bool>(ud)
+            })
+        });
+        let status_line = Self::setup_lines(lua, &rx_table, "status_line")?;
+        let header = Self::setup_lines(lua, &rx_table, "header")?;
+        let status_segments = Self::setup_status_segments(lua, &rx_table)?;
+        Ok(ListingHooks { sorter, filter, status_line, header, status_segments })
+    }
+
+    /// Wraps the functions set with `rx.setStatusLine` or `rx.setHeader`.
+    fn setup_lines(lua: &Lua, rx_table: &mlua::Table, key: &str) -> Result<Vec<LineFn>> {
+        let parts: Option<Vec<mlua::Function>> = rx_table.get(key).map_err(ExplorerError::LuaError)?;
+        Ok(parts.unwrap_or_default().into_iter().map(|func| Self::line_fn(lua, func)).collect())
+    }
+
+    /// Wraps the segments added with `rx.addStatusSegment`.
+    fn setup_status_segments(lua: &Lua, rx_table: &mlua::Table) -> Result<HashMap<String, LineFn>> {
+        let segments: Option<mlua::Table> = rx_table.get("status_segments").map_err(ExplorerError::LuaError)?;
+        let Some(segments) = segments else {
+            return Ok(HashMap::new());
+        };
+
+        let mut status_segments = HashMap::new();
+        for pair in segments.pairs::<String, mlua::Function>() {
+            let (name, func) = pair.map_err(ExplorerError::LuaError)?;
+            status_segments.insert(name, Self::line_fn(lua, func));
+        }
+        Ok(status_segments)
+    }
+
+    /// Wraps a function giving a line or a segment of the status bar. It
+    /// gets a table describing the listing.
+    fn line_fn(lua: &Lua, func: mlua::Function) -> LineFn {
+        let lua = lua.clone();
+        Box::new(move |context: &LineContext| {
+            let table = || -> mlua::Result<mlua::Table> {
+                let table = lua.create_table()?;
+                table.set("path", context.path.clone())?;
+                table.set("entry", lua.create_userdata(context.entry.clone())?)?;
+                table.set("selection", context.selection.clone())?;
+                table.set("mode", context.mode)?;
+                table.set("listing", context.listing)?;
+                Ok(table)
+            };
+            table().and_then(|table| func.call::<String>(table)).unwrap_or_default()
+        })
+    }
+
+    /// Reads the display modules in `rx[key]`, given as functions or column
+    /// specs.
+    fn setup_display_modules(lua: &Lua, key: &str) -> Result<Vec<Column>> {
         let rx_table: mlua::Table = lua.globals()
             .get("rx")
             .map_err(ExplorerError::LuaError)?;
         
-        let modules_table: mlua::Table = rx_table
-            .get("modules")
+        let modules_table: Option<mlua::Table> = rx_table
+            .get(key)
             .map_err(ExplorerError::LuaError)?;
+        let Some(modules_table) = modules_table else {
+            return Ok(Vec::new());
+        };
+
+        let mut columns = Vec::new();
+        for value in modules_table.sequence_values::<mlua::Value>() {
+            let value = value.map_err(ExplorerError::LuaError)?;
+            columns.push(Self::column_from_lua(lua, value).map_err(ExplorerError::LuaError)?);
+        }
+        
+        Ok(columns)
+    }
 
-        let mut display_modules = Vec::new();
-        for pair in modules_table.pairs::<mlua::Value, mlua::Function>() {
-            let (_, func) = pair.map_err(ExplorerError::LuaError)?;
+    /// Reads a module given as a function, or as a column spec like
+    /// `{ rx.Size, align = "right", max_width = 8, title = "Size", priority = 1 }`.
+    fn column_from_lua(lua: &Lua, value: mlua::Value) -> mlua::Result<Column> {
+        let (func, spec) = match value {
+            mlua::Value::Function(func) => (func, None),
+            mlua::Value::Table(spec) => (spec.get::<mlua::Function>(1)?, Some(spec)),
+            _ => return Err(mlua::Error::RuntimeError("Invalid module type".into())),
+        };
+        // The built-in modules sort like the matching sort orders
+        let rx_table: mlua::Table = lua.globals().get("rx")?;
+        let mut sort = ColumnSort::Output;
+        for (name, key) in [("Name", SortKey::Natural), ("Size", SortKey::Size), ("CreationDate", SortKey::Created)] {
+            if rx_table.get::<mlua::Function>(name)? == func {
+                sort = ColumnSort::Key(key);
+            }
+        }
+        let lua_clone = lua.clone();
+        let display_fn: DisplayModuleFn = Box::new(move |entry: &Entry| {
+            let ud = lua_clone.create_userdata(entry.clone()).unwrap();
+            func.call::<StyledText>(ud).unwrap_or_default()
+        });
+        let mut column = Column::new(display_fn);
+        column.sort = sort;
+        let Some(spec) = spec else {
+            return Ok(column);
+        };
+        if let Some(align) = spec.get::<Option<String>>("align")? {
+            column.align = Align::from_name(&align)
+                .ok_or_else(|| mlua::Error::RuntimeError(format!("Unknown alignment: {}", align)))?;
+        }
+        column.min_width = spec.get::<Option<usize>>("min_width")?.unwrap_or(0);
+        column.max_width = spec.get("max_width")?;
+        column.title = spec.get("title")?;
+        column.priority = spec.get("priority")?;
+        if let Some(key) = spec.get::<Option<mlua::Function>>("key")? {
             let lua_clone = lua.clone();
-            let display_fn: DisplayModuleFn = Box::new(move |entry: &Entry| {
+            column.sort = ColumnSort::Custom(Box::new(move |entry: &Entry| {
                 let ud = lua_clone.create_userdata(entry.clone()).unwrap();
-                func.call::<_>(ud).unwrap_or_default()
-            });
-            display_modules.push(display_fn);
+                key.call::<SortValue>(ud).unwrap_or(SortValue::Text(String::new()))
+            }));
         }
-        
-        Ok(display_modules)
+        Ok(column)
     }
 
     fn get_theme(lua: &Lua) -> Result<Theme> {
@@ -152,6 +312,7 @@ impl FileExplorer {
                 selected_fg: Color::Yellow,
                 selected_bg: Color::DarkGrey,
                 highlight: Color::Green,
+                git: GitColors::default(),
             })
         }
     }
@@ -179,34 +340,274 @@ impl FileExplorer {
     fn set_title<W: Write>(&self, writer: &mut W) {
         execute!(writer, SetTitle(format!(
             "rx - {}",
-            self.state.current_path.display()
+            self.state().current_path.display()
         ))).unwrap();
     }
 
+    /// Runs the explorer until the user leaves it. Returns the directory to
+    /// change to when leaving with "quit and cd", and `None` on a plain quit.
     pub fn run(&mut self) -> Result<Option<PathBuf>> {
+        let ipc = if self.state().config.ipc {
+            Self::start_ipc()
+        } else {
+            None
+        };
+
         let mut writer = Self::setup_terminal(self.is_tty_mode)?;
         self.set_title(&mut writer);
 
-        let result = self.run_event_loop(&mut writer);
+        let result = self.run_event_loop(&mut writer, ipc.as_ref());
         
         Self::cleanup_terminal(&mut writer)?;
         
         result
     }
     
-    fn run_event_loop<W: Write>(&mut self, writer: &mut W) -> Result<Option<PathBuf>> {
+    fn start_ipc() -> Option<IpcServer> {
+        let path = IpcServer::socket_path();
+        // SAFETY: no other thread runs yet, the server thread is only
+        // spawned by `start`
+        unsafe { std::env::set_var("RX_SOCKET", &path) };
+        match IpcServer::start(path) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                // SAFETY: `start` failed before spawning its thread
+                unsafe { std::env::remove_var("RX_SOCKET") };
+                eprintln!("Failed to start IPC server: {}", e);
+                None
+            }
+        }
+    }
+
+    fn run_event_loop<W: Write>(&mut self, writer: &mut W, ipc: Option<&IpcServer>) -> Result<Option<PathBuf>> {
         loop {
             if self.dirty {
-                self.renderer.render(writer, &self.state);
+                self.layout()?;
+                self.renderer.render(writer, &self.tabs, self.active_tab, self.other_pane, self.columns.as_ref());
                 self.dirty = false;
             }
 
-            let event = crossterm::event::read()?;
-            if let Some(path) = InputHandler::handle_event(event, &mut self.state, &mut self.renderer, writer)? {
-                return Ok(Some(path));
+            // Wake up regularly to serve remote commands and add search
+            // results, otherwise wait for the next event
+            let waiting = ipc.is_some() || self.tabs.iter().any(AppState::is_searching);
+            if !waiting || crossterm::event::poll(Duration::from_millis(50))? {
+                let event = crossterm::event::read()?;
+                let clicked = match event {
+                    Event::Mouse(MouseEvent { kind: MouseEventKind::Down(_), column, .. }) => Some(column),
+                    _ => None,
+                };
+                // Clicks on the side columns of the columns view are ignored
+                if clicked.is_none_or(|column| !self.renderer.is_outside_list(column)) {
+                    if let Some(column) = clicked {
+                        self.focus_pane_at(column)?;
+                    }
+                    let state = &mut self.tabs[self.active_tab];
+                    match InputHandler::handle_event(event, state, &self.renderer, writer)? {
+                        Some(ExplorerAction::Quit) => return Ok(None),
+                        Some(ExplorerAction::QuitAndCd(path)) => return Ok(Some(path)),
+                        Some(action) => {
+                            self.handle_action(action)?;
+                            self.set_title(writer);
+                        }
+                        None => {}
+                    }
+                    self.dirty = true;
+                }
+            }
+
+            while let Some((request, reply)) = ipc.and_then(IpcServer::try_recv) {
+                let _ = reply.send(self.handle_request(request));
+                self.set_title(writer);
+                self.dirty = true;
+            }
+
+            for state in &mut self.tabs {
+                self.dirty |= state.poll_finder();
             }
-            
-            self.dirty = true;
         }
     }
-}
\ No newline at end of file
+
+    fn state(&self) -> &AppState {
+        &self.tabs[self.active_tab]
+    }
+
+    fn state_mut(&mut self) -> &mut AppState {
+        &mut self.tabs[self.active_tab]
+    }
+
+    /// Fits the viewports of the visible tabs to the space left by the tab
+    /// bar, and reloads them if another tab changed files.
+    fn layout(&mut self) -> Result<()> {
+        let view = if self.other_pane.is_some() {
+            View::DualPane
+        } else if self.columns.is_some() {
+            View::Columns
+        } else {
+            View::Single
+        };
+        let show_header = !self.state().hooks.header.is_empty();
+        // The side columns line up with the active tab
+        let side_titles = view == View::Columns && self.column_modules.iter().any(|column| column.title.is_some());
+        let show_titles = self.state().has_titles() || side_titles;
+        self.renderer.set_layout(self.tabs.len(), view, show_header, show_titles);
+        let height = self.renderer.list_height();
+        for index in std::iter::once(self.active_tab).chain(self.other_pane) {
+            let state = &mut self.tabs[index];
+            if state.is_outdated() {
+                state.refresh()?;
+            }
+            state.viewport.resize(height);
+            state.viewport.update(state.selected, state.entries.len());
+        }
+        self.state_mut().update_lines();
+        if let Some(columns) = &mut self.columns {
+            columns.update(&self.tabs[self.active_tab], &self.column_modules)?;
+            columns.resize(height);
+        }
+        Ok(())
+    }
+
+    fn handle_action(&mut self, action: ExplorerAction) -> Result<()> {
+        match action {
+            ExplorerAction::NewTab => {
+                let tab = self.state().new_tab(&self.state().current_path)?;
+                let index = self.active_tab + 1;
+                self.tabs.insert(index, tab);
+                if let Some(other) = self.other_pane.as_mut().filter(|other| **other >= index) {
+                    *other += 1;
+                }
+                self.switch_tab(index)?;
+            }
+            ExplorerAction::CloseTab => self.close_tab()?,
+            ExplorerAction::NextTab | ExplorerAction::PreviousTab if self.other_pane.is_some() => {
+                self.swap_panes()?;
+            }
+            ExplorerAction::NextTab => {
+                self.switch_tab((self.active_tab + 1) % self.tabs.len())?;
+            }
+            ExplorerAction::PreviousTab => {
+                self.switch_tab((self.active_tab + self.tabs.len() - 1) % self.tabs.len())?;
+            }
+            ExplorerAction::SwitchTab(index) => {
+                if self.other_pane == Some(index) {
+                    self.swap_panes()?;
+                } else if index < self.tabs.len() {
+                    self.switch_tab(index)?;
+                }
+            }
+            ExplorerAction::ToggleDualPane => {
+                if self.other_pane.take().is_none() {
+                    self.columns = None;
+                    if self.tabs.len() == 1 {
+                        let tab = self.state().new_tab(&self.state().current_path)?;
+                        self.tabs.push(tab);
+                    }
+                    let other = if self.active_tab + 1 < self.tabs.len() {
+                        self.active_tab + 1
+                    } else {
+                        self.active_tab - 1
+                    };
+                    self.other_pane = Some(other);
+                }
+            }
+            ExplorerAction::ToggleColumns => {
+                if self.columns.take().is_none() {
+                    self.other_pane = None;
+                    self.columns = Some(MillerColumns::new());
+                }
+            }
+            ExplorerAction::Transfer(mode) => {
+                let target = match self.other_pane {
+                    Some(other) => self.tabs[other].current_path.clone(),
+                    None => self.state().current_path.clone(),
+                };
+                let state = self.state_mut();
+                if !state.marked_or_selected().is_empty() {
+                    state.prompt.set_mode_with_text(mode, &target.join("").display().to_string());
+                }
+            }
+            ExplorerAction::Quit | ExplorerAction::QuitAndCd(_) => {}
+        }
+        Ok(())
+    }
+
+    fn close_tab(&mut self) -> Result<()> {
+        if self.tabs.len() == 1 {
+            return Ok(());
+        }
+        let closed = self.active_tab;
+        self.tabs.remove(closed);
+        self.other_pane = self.other_pane.map(|other| if other > closed { other - 1 } else { other });
+
+        let mut next = closed.min(self.tabs.len() - 1);
+        if self.other_pane == Some(next) {
+            if self.tabs.len() == 1 {
+                self.other_pane = None;
+            } else if next + 1 < self.tabs.len() {
+                next += 1;
+            } else {
+                next -= 1;
+            }
+        }
+        self.switch_tab(next)
+    }
+
+    /// Makes the other pane the active one in dual-pane mode.
+    fn swap_panes(&mut self) -> Result<()> {
+        if let Some(other) = self.other_pane.replace(self.active_tab) {
+            self.switch_tab(other)?;
+        }
+        Ok(())
+    }
+
+    /// Activates the pane under a mouse click in dual-pane mode.
+    fn focus_pane_at(&mut self, column: u16) -> Result<()> {
+        if let Some(other) = self.other_pane {
+            let active_is_right = self.active_tab > other;
+            if self.renderer.is_right_pane(column) != active_is_right {
+                self.swap_panes()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn switch_tab(&mut self, index: usize) -> Result<()> {
+        self.active_tab = index;
+        let state = self.state_mut();
+        std::env::set_current_dir(&state.current_path)?;
+        // The shared history may have changed files shown here from another tab
+        state.refresh()
+    }
+
+    fn handle_request(&mut self, request: Request) -> Response {
+        let state = self.state_mut();
+        let result = match request {
+            Request::Cd(path) => state.change_dir(&path),
+            Request::Select(path) => file_ops::canonicalize_parent(&path).and_then(|path| {
+                let parent = path.parent().unwrap_or(&path);
+                if parent != state.current_path {
+                    state.change_dir(parent)?;
+                }
+                state.select_path(&path);
+                match state.entries.get(state.selected) {
+                    Some(selected) if *selected == path => Ok(()),
+                    _ => Err(ExplorerError::Other(format!("{} is not listed", path.display()))),
+                }
+            }),
+            Request::Refresh => state.refresh(),
+            Request::GetCwd => {
+                return Response::Value(state.current_path.display().to_string());
+            }
+            Request::GetSelection => {
+                return match state.selected {
+                    0 => Response::Ok,
+                    i => Response::Value(state.entries[i].display().to_string()),
+                };
+            }
+        };
+        match result {
+            Ok(()) => Response::Ok,
+            Err(e) => Response::Error(e.to_string()),
+        }
+    }
+}
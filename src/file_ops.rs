@@ -1,28 +1,142 @@
+use std::collections::HashSet;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use ignore::WalkBuilder;
+use ignore::overrides::OverrideBuilder;
 
 use crate::error::{ExplorerError, Result};
 use crate::history::{Operation, backup_dir, DirBackup};
+use crate::sort::SortOrder;
 
-pub fn read_dir_entries(path: &Path) -> Result<Vec<PathBuf>> {
+/// Resolves the path given on the command line into the directory to open
+/// and, when the path points to a file, the entry to select in it.
+pub fn resolve_start_path(path: &Path) -> Result<(PathBuf, Option<PathBuf>)> {
+    if !path.exists() {
+        return Err(ExplorerError::InvalidPath(path.to_path_buf()));
+    }
+    if path.is_dir() {
+        return Ok((path.canonicalize()?, None));
+    }
+
+    let selected = canonicalize_parent(path)?;
+    let parent = selected.parent().map_or_else(|| selected.clone(), Path::to_path_buf);
+    Ok((parent, Some(selected)))
+}
+
+/// Canonicalizes the parent of `path` only, so a symlinked file keeps its own
+/// name rather than resolving to its target.
+pub fn canonicalize_parent(path: &Path) -> Result<PathBuf> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.canonicalize()?,
+        _ => std::env::current_dir()?,
+    };
+    let file_name = path.file_name()
+        .ok_or_else(|| ExplorerError::InvalidPath(path.to_path_buf()))?;
+    Ok(parent.join(file_name))
+}
+
+/// Which entries the directory listings leave out.
+#[derive(Clone, Copy)]
+pub struct Visibility {
+    /// Show the entries whose name starts with a dot
+    pub show_hidden: bool,
+    /// Leave out the paths ignored by the `.gitignore` and `.ignore` files
+    /// of the enclosing repository
+    pub hide_ignored: bool,
+}
+
+pub fn read_dir_entries(path: &Path, order: &SortOrder, visibility: Visibility) -> Result<Vec<PathBuf>> {
     let mut entries = vec![path.join("..")];
-    let mut dirs = Vec::new();
-    let mut files = Vec::new();
-
-    for entry in fs::read_dir(path)?.filter_map(|e| e.ok()) {
-        let path = entry.path();
-        if path.is_dir() {
-            dirs.push(path);
-        } else {
-            files.push(path);
+    let mut children: Vec<PathBuf> = fs::read_dir(path)?
+        .filter_map(|e| e.ok())
+        .filter(|entry| visibility.show_hidden || !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| entry.path())
+        .collect();
+    if visibility.hide_ignored {
+        let kept = unignored_children(path);
+        children.retain(|child| kept.contains(child));
+    }
+    order.sort(&mut children);
+    entries.extend(children);
+    Ok(entries)
+}
+
+/// The children of `path` that no ignore rule applies to, reading the rules
+/// of the parent directories as well.
+fn unignored_children(path: &Path) -> HashSet<PathBuf> {
+    WalkBuilder::new(path)
+        .max_depth(Some(1))
+        .standard_filters(false)
+        .git_ignore(true)
+        .git_global(true)
+        .git_exclude(true)
+        .ignore(true)
+        .parents(true)
+        .build()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .collect()
+}
+
+/// Like `read_dir_entries`, with the contents of the `expanded` directories
+/// listed right after them. Directories that can't be read stay collapsed.
+pub fn read_tree_entries(
+    path: &Path,
+    expanded: &[PathBuf],
+    order: &SortOrder,
+    visibility: Visibility,
+) -> Result<Vec<PathBuf>> {
+    let mut entries = read_dir_entries(path, order, visibility)?;
+    let mut index = 1;
+    while index < entries.len() {
+        let entry = &entries[index];
+        let children = expanded.contains(entry).then(|| read_dir_entries(entry, order, visibility).ok()).flatten();
+        if let Some(children) = children {
+            entries.splice(index + 1..index + 1, children.into_iter().skip(1));
         }
+        index += 1;
+    }
+    Ok(entries)
+}
+
+/// Walks the tree below `path`, skipping the paths matched by `.gitignore`
+/// files or by one of the `ignore` globs, and hidden files unless
+/// `show_hidden` is set.
+pub fn walk_builder(path: &Path, ignore: &[String], show_hidden: bool) -> Result<WalkBuilder> {
+    let mut overrides = OverrideBuilder::new(path);
+    for pattern in ignore {
+        overrides
+            .add(&format!("!{}", pattern))
+            .map_err(|e| ExplorerError::Config(e.to_string()))?;
     }
+    let overrides = overrides.build().map_err(|e| ExplorerError::Config(e.to_string()))?;
+
+    let mut builder = WalkBuilder::new(path);
+    builder.overrides(overrides).hidden(!show_hidden);
+    Ok(builder)
+}
 
-    dirs.sort_by_key(|a| a.file_name().unwrap_or_default().to_string_lossy().to_lowercase());
-    files.sort_by_key(|a| a.file_name().unwrap_or_default().to_string_lossy().to_lowercase());
+/// Lists every file below `path`, at most `max_depth` directories deep.
+/// Paths matched by `.gitignore` files or by one of the `ignore` globs are
+/// left out, and hidden files unless `visibility` shows them.
+pub fn read_recursive_entries(
+    path: &Path,
+    max_depth: Option<usize>,
+    ignore: &[String],
+    order: &SortOrder,
+    visibility: Visibility,
+) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = walk_builder(path, ignore, visibility.show_hidden)?
+        .max_depth(max_depth)
+        .build()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_some_and(|file_type| !file_type.is_dir()))
+        .map(|e| e.into_path())
+        .collect();
+    order.sort(&mut files);
 
-    entries.extend(dirs);
+    let mut entries = vec![path.join("..")];
     entries.extend(files);
     Ok(entries)
 }
@@ -64,6 +178,32 @@ pub fn open_file_in_editor(path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Opens `path` at `line` in `$EDITOR`, falling back to `xdg-open`, which
+/// can't jump to a line, when it isn't set.
+pub fn open_file_at_line(path: &Path, line: usize) -> Result<()> {
+    let editor = std::env::var("EDITOR").unwrap_or_default();
+    let mut words = editor.split_whitespace();
+    let Some(program) = words.next() else {
+        return open_file_in_editor(path);
+    };
+
+    let mut command = Command::new(program);
+    command.args(words).arg(format!("+{}", line)).arg(path);
+    // Output may be piped, talk to the terminal directly
+    if let Ok(tty) = fs::File::options().read(true).write(true).open("/dev/tty") {
+        command.stdin(tty.try_clone()?).stdout(tty.try_clone()?).stderr(tty);
+    }
+    let status = command
+        .status()
+        .map_err(|e| ExplorerError::OperationFailed(format!("Failed to open editor: {}", e)))?;
+
+    if !status.success() {
+        return Err(ExplorerError::OperationFailed("Editor exited with non-zero status".into()));
+    }
+
+    Ok(())
+}
+
 pub fn prepare_delete_operation(path: &Path, position: usize) -> Result<Operation> {
     let is_dir = path.is_dir();
     let content = if !is_dir {
@@ -119,6 +259,51 @@ pub fn restore_deleted_path(path: &Path, is_dir: bool, content: &Option<Vec<u8>>
 }
 
 pub fn rename_path(old_path: &Path, new_path: &Path) -> Result<()> {
-    fs::rename(old_path, new_path)?;
+    match fs::rename(old_path, new_path) {
+        // Moving to another filesystem, fall back to copying
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_path(old_path, new_path)?;
+            delete_path(old_path, old_path.is_dir())
+        }
+        result => Ok(result?),
+    }
+}
+
+/// Copies a file or a directory tree. Symlinks are copied as links rather
+/// than as the files they point to.
+pub fn copy_path(source: &Path, destination: &Path) -> Result<()> {
+    let file_type = fs::symlink_metadata(source)?.file_type();
+    if file_type.is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(source)?, destination)?;
+    } else if file_type.is_dir() {
+        fs::create_dir_all(destination)?;
+        for entry in fs::read_dir(source)? {
+            let entry = entry?;
+            copy_path(&entry.path(), &destination.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(source, destination)?;
+    }
     Ok(())
 }
+
+/// Where `source` ends up when copied or moved to `target`: inside it when
+/// it is a directory, or at `target` itself otherwise.
+pub fn transfer_destination(source: &Path, target: &Path) -> Result<PathBuf> {
+    let destination = if target.is_dir() {
+        let parent = source.parent().and_then(|parent| parent.canonicalize().ok());
+        if parent.is_some() && parent == target.canonicalize().ok() {
+            return Err(ExplorerError::OperationFailed(format!("{} is already in {}", source.display(), target.display())));
+        }
+        target.join(source.file_name().unwrap_or_default())
+    } else {
+        target.to_path_buf()
+    };
+    if destination.exists() {
+        return Err(ExplorerError::OperationFailed(format!("{} already exists", destination.display())));
+    }
+    if destination.starts_with(source) {
+        return Err(ExplorerError::OperationFailed(format!("Cannot copy {} into itself", source.display())));
+    }
+    Ok(destination)
+}
@@ -0,0 +1,166 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use ignore::DirEntry;
+use regex::Regex;
+
+use crate::error::Result;
+use crate::file_ops;
+
+/// Longest snippet kept of a matching line
+const MAX_SNIPPET_CHARS: usize = 200;
+
+/// A search result.
+#[derive(Clone)]
+pub struct Hit {
+    pub path: PathBuf,
+    /// The matching line, for content searches
+    pub line: Option<LineMatch>,
+}
+
+#[derive(Clone)]
+pub struct LineMatch {
+    /// Line number, starting at 1
+    pub number: usize,
+    pub text: String,
+}
+
+/// A recursive search running in the background. The search stops when the
+/// finder is dropped.
+pub struct Finder {
+    receiver: Receiver<Hit>,
+    cancelled: Arc<AtomicBool>,
+    done: bool,
+    /// Said about the search on the status line
+    pub notice: Option<String>,
+}
+
+impl Finder {
+    /// Starts looking for the paths below `root` whose name contains `query`,
+    /// ignoring case. The paths matched by `.gitignore` files or the `ignore`
+    /// globs are skipped, and hidden files unless `show_hidden` is set.
+    pub fn names(root: &Path, query: &str, ignore: &[String], show_hidden: bool) -> Result<Self> {
+        let query = query.to_lowercase();
+        Self::start(root, ignore, show_hidden, move |entry, sender| {
+            let name = entry.file_name().to_string_lossy().to_lowercase();
+            !name.contains(&query) || sender.send(Hit { path: entry.into_path(), line: None }).is_ok()
+        })
+    }
+
+    /// Starts looking for the lines matching `pattern` in the files below
+    /// `root`, skipping binary files and the same paths as `names`. A pattern
+    /// that isn't a valid regex is searched as plain text, with a notice.
+    pub fn contents(root: &Path, pattern: &str, ignore: &[String], show_hidden: bool) -> Result<Self> {
+        let (regex, notice) = match Regex::new(pattern) {
+            Ok(regex) => (regex, None),
+            Err(_) => {
+                let regex = Regex::new(&regex::escape(pattern)).expect("an escaped pattern is a valid regex");
+                (regex, Some(format!("Invalid regex, searching for the text {:?}", pattern)))
+            }
+        };
+        let mut finder = Self::start(root, ignore, show_hidden, move |entry, sender| {
+            if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+                return true;
+            }
+            let Ok(file) = File::open(entry.path()) else {
+                return true;
+            };
+            // Files are read line by line, only their start is checked for
+            // binary content
+            let mut reader = BufReader::new(file);
+            match reader.fill_buf() {
+                Ok(start) if !is_binary(start) => {}
+                _ => return true,
+            }
+            let mut line = Vec::new();
+            for number in 1.. {
+                line.clear();
+                if !matches!(reader.read_until(b'\n', &mut line), Ok(read) if read > 0) {
+                    break;
+                }
+                let text = String::from_utf8_lossy(&line);
+                if !regex.is_match(text.trim_end_matches(['\n', '\r'])) {
+                    continue;
+                }
+                let hit = Hit {
+                    path: entry.path().to_path_buf(),
+                    line: Some(LineMatch {
+                        number,
+                        text: text.trim().chars().take(MAX_SNIPPET_CHARS).collect(),
+                    }),
+                };
+                if sender.send(hit).is_err() {
+                    return false;
+                }
+            }
+            true
+        })?;
+        finder.notice = notice;
+        Ok(finder)
+    }
+
+    /// Walks the tree below `root` on a worker thread, handing each entry to
+    /// `search` until it returns false.
+    fn start<F>(root: &Path, ignore: &[String], show_hidden: bool, search: F) -> Result<Self>
+    where
+        F: Fn(DirEntry, &Sender<Hit>) -> bool + Send + 'static,
+    {
+        let walker = file_ops::walk_builder(root, ignore, show_hidden)?.build();
+        let (sender, receiver) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let cancelled_flag = cancelled.clone();
+        thread::spawn(move || {
+            // The root itself is not a result
+            for entry in walker.skip(1).filter_map(|e| e.ok()) {
+                if cancelled_flag.load(Ordering::Relaxed) || !search(entry, &sender) {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            receiver,
+            cancelled,
+            done: false,
+            notice: None,
+        })
+    }
+
+    /// Returns the hits found since the last call.
+    pub fn poll(&mut self) -> Vec<Hit> {
+        let mut found = Vec::new();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(hit) => found.push(hit),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+        found
+    }
+
+    /// Whether the whole tree has been searched.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+impl Drop for Finder {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Files with a NUL byte in their first 8000 bytes are taken for binaries,
+/// like git does.
+fn is_binary(content: &[u8]) -> bool {
+    content.iter().take(8000).any(|&byte| byte == 0)
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use crate::error::{ExplorerError, Result};
+use crate::file_ops;
+
+/// State of a path in its git repository, from the least to the most
+/// important.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GitStatus {
+    Ignored,
+    Untracked,
+    /// Changes are staged, and the work tree matches the index
+    Staged,
+    /// The work tree differs from the index
+    Modified,
+    /// Unmerged, with conflicts to resolve
+    Conflicted,
+}
+
+impl GitStatus {
+    /// Name of the status, as exposed to Lua.
+    pub fn name(self) -> &'static str {
+        match self {
+            GitStatus::Ignored => "ignored",
+            GitStatus::Untracked => "untracked",
+            GitStatus::Staged => "staged",
+            GitStatus::Modified => "modified",
+            GitStatus::Conflicted => "conflicted",
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            GitStatus::Ignored => "I",
+            GitStatus::Untracked => "?",
+            GitStatus::Staged => "S",
+            GitStatus::Modified => "M",
+            GitStatus::Conflicted => "!",
+        }
+    }
+
+    /// Parses the two status letters of a `git status --porcelain` line.
+    fn from_porcelain(index: u8, worktree: u8) -> Option<Self> {
+        match (index, worktree) {
+            (b'?', b'?') => Some(GitStatus::Untracked),
+            (b'!', b'!') => Some(GitStatus::Ignored),
+            (b'U', _) | (_, b'U') | (b'A', b'A') | (b'D', b'D') => Some(GitStatus::Conflicted),
+            (_, b' ') if index != b' ' => Some(GitStatus::Staged),
+            (_, b' ') => None,
+            _ => Some(GitStatus::Modified),
+        }
+    }
+}
+
+/// The checked out branch and how far it is from its upstream.
+pub struct Branch {
+    pub name: String,
+    pub ahead: usize,
+    pub behind: usize,
+}
+
+impl Branch {
+    /// Parses the `## main...origin/main [ahead 1, behind 2]` header of
+    /// `git status --branch`.
+    fn from_header(header: &str) -> Self {
+        let header = header.trim_start_matches("## ");
+        let header = header.strip_prefix("No commits yet on ").unwrap_or(header);
+        let (name, tracking) = header.split_once(' ').unwrap_or((header, ""));
+        let name = name.split_once("...").map_or(name, |(local, _)| local);
+        let count = |label: &str| {
+            tracking
+                .trim_matches(|c| c == '[' || c == ']')
+                .split(", ")
+                .find_map(|part| part.strip_prefix(label)?.parse().ok())
+                .unwrap_or(0)
+        };
+        Self {
+            name: name.to_string(),
+            ahead: count("ahead "),
+            behind: count("behind "),
+        }
+    }
+}
+
+/// The statuses of the changed paths below a directory of a repository.
+pub struct GitStatuses {
+    pub branch: Option<Branch>,
+    root: PathBuf,
+    paths: HashMap<PathBuf, GitStatus>,
+    /// Most important status of the paths below each directory
+    directories: HashMap<PathBuf, GitStatus>,
+}
+
+impl GitStatuses {
+    /// Runs `git status` on `path`, once for the whole directory. Returns
+    /// `None` outside of a repository or when git can't be run.
+    pub fn load(path: &Path) -> Option<Self> {
+        let root = path.ancestors().find(|dir| dir.join(".git").exists())?.to_path_buf();
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(path)
+            .args(["status", "--porcelain", "-z", "--branch", "--ignored", "--", "."])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())?;
+        Some(Self::parse(root, &output.stdout))
+    }
+
+    /// Reads the output of `git status --porcelain -z --branch` run in the
+    /// repository at `root`.
+    fn parse(root: PathBuf, output: &[u8]) -> Self {
+        let mut statuses = Self {
+            branch: None,
+            root,
+            paths: HashMap::new(),
+            directories: HashMap::new(),
+        };
+        let mut fields = output.split(|&byte| byte == 0);
+        while let Some(field) = fields.next() {
+            if field.starts_with(b"## ") {
+                statuses.branch = Some(Branch::from_header(&String::from_utf8_lossy(field)));
+                continue;
+            }
+            if field.len() < 4 {
+                continue;
+            }
+            // Renames and copies are followed by the original path
+            if matches!(field[0], b'R' | b'C') {
+                fields.next();
+            }
+            let Some(status) = GitStatus::from_porcelain(field[0], field[1]) else {
+                continue;
+            };
+            // Paths are relative to the root of the repository, and
+            // untracked or ignored directories end with a slash
+            let relative = String::from_utf8_lossy(&field[3..]);
+            let path = statuses.root.join(relative.trim_end_matches('/'));
+            statuses.add(path, status);
+        }
+        statuses
+    }
+
+    fn add(&mut self, path: PathBuf, status: GitStatus) {
+        // An ignored file doesn't make its directory look ignored
+        if status != GitStatus::Ignored {
+            for dir in path.ancestors().skip(1).take_while(|dir| *dir != self.root) {
+                let current = self.directories.entry(dir.to_path_buf()).or_insert(status);
+                *current = (*current).max(status);
+            }
+        }
+        self.paths.insert(path, status);
+    }
+
+    /// Status of `path`, which is inherited from untracked and ignored
+    /// directories. A directory gets the most important status of the paths
+    /// below it.
+    pub fn get(&self, path: &Path) -> Option<GitStatus> {
+        // The `..` entry stands for the parent, not for the directory itself
+        if path.ends_with("..") {
+            return None;
+        }
+        if let Some(&status) = self.paths.get(path) {
+            return Some(status);
+        }
+        let inherited = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(&self.root) && *dir != self.root)
+            .find_map(|dir| self.paths.get(dir).copied());
+        inherited.or_else(|| self.directories.get(path).copied())
+    }
+}
+
+/// Stages the changes of `paths`.
+pub fn stage(paths: &[PathBuf]) -> Result<()> {
+    run(&["add", "--"], paths)
+}
+
+/// Removes the changes of `paths` from the index, keeping them in the work
+/// tree.
+pub fn unstage(paths: &[PathBuf]) -> Result<()> {
+    run(&["restore", "--staged", "--"], paths)
+}
+
+/// Reverts the unstaged changes of `paths` to the staged version.
+pub fn discard(paths: &[PathBuf]) -> Result<()> {
+    run(&["restore", "--"], paths)
+}
+
+/// Renames a file with `git mv` when it is tracked, so that the index
+/// follows. Other files are renamed as usual. An existing file is never
+/// replaced.
+pub fn rename(old_path: &Path, new_path: &Path) -> Result<()> {
+    if new_path.exists() || new_path.is_symlink() {
+        return Err(ExplorerError::OperationFailed(format!("{} already exists", new_path.display())));
+    }
+    if is_tracked(old_path) {
+        return run(&["mv", "--"], &[old_path.to_path_buf(), new_path.to_path_buf()]);
+    }
+    file_ops::rename_path(old_path, new_path)
+}
+
+fn is_tracked(path: &Path) -> bool {
+    git_in(path)
+        .args(["ls-files", "--error-unmatch", "--"])
+        .arg(path)
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+/// Runs a git command on `paths`, from the directory of the first one.
+fn run(args: &[&str], paths: &[PathBuf]) -> Result<()> {
+    let Some(first) = paths.first() else {
+        return Ok(());
+    };
+    let output = git_in(first).args(args).args(paths).output()?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(ExplorerError::OperationFailed(message.trim().to_string()));
+    }
+    Ok(())
+}
+
+/// A git command run from the directory holding `path`.
+fn git_in(path: &Path) -> Command {
+    let mut command = Command::new("git");
+    if let Some(parent) = path.parent() {
+        command.arg("-C").arg(parent);
+    }
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn porcelain_letters() {
+        assert!(GitStatus::from_porcelain(b'?', b'?') == Some(GitStatus::Untracked));
+        assert!(GitStatus::from_porcelain(b'!', b'!') == Some(GitStatus::Ignored));
+        assert!(GitStatus::from_porcelain(b'M', b' ') == Some(GitStatus::Staged));
+        assert!(GitStatus::from_porcelain(b'R', b' ') == Some(GitStatus::Staged));
+        assert!(GitStatus::from_porcelain(b' ', b'M') == Some(GitStatus::Modified));
+        assert!(GitStatus::from_porcelain(b'A', b'M') == Some(GitStatus::Modified));
+        assert!(GitStatus::from_porcelain(b' ', b'D') == Some(GitStatus::Modified));
+        assert!(GitStatus::from_porcelain(b' ', b' ').is_none());
+    }
+
+    #[test]
+    fn unmerged_paths_are_conflicted() {
+        for (index, worktree) in [(b'U', b'U'), (b'A', b'U'), (b'U', b'D'), (b'A', b'A'), (b'D', b'D')] {
+            assert!(GitStatus::from_porcelain(index, worktree) == Some(GitStatus::Conflicted));
+        }
+    }
+
+    #[test]
+    fn branch_with_upstream() {
+        let branch = Branch::from_header("## main...origin/main [ahead 2, behind 3]");
+        assert_eq!(branch.name, "main");
+        assert_eq!((branch.ahead, branch.behind), (2, 3));
+
+        let branch = Branch::from_header("## main...origin/main [behind 1]");
+        assert_eq!((branch.ahead, branch.behind), (0, 1));
+    }
+
+    #[test]
+    fn branch_without_upstream() {
+        let branch = Branch::from_header("## feature/x");
+        assert_eq!(branch.name, "feature/x");
+        assert_eq!((branch.ahead, branch.behind), (0, 0));
+    }
+
+    #[test]
+    fn branch_without_commits() {
+        let branch = Branch::from_header("## No commits yet on main");
+        assert_eq!(branch.name, "main");
+        assert_eq!((branch.ahead, branch.behind), (0, 0));
+    }
+
+    #[test]
+    fn renames_skip_the_original_path() {
+        let root = PathBuf::from("/repo");
+        let output = b"## main\0R  new.txt\0old.txt\0C  copy.txt\0orig.txt\0 M b.txt\0";
+        let statuses = GitStatuses::parse(root.clone(), output);
+        assert!(statuses.get(&root.join("new.txt")) == Some(GitStatus::Staged));
+        assert!(statuses.get(&root.join("copy.txt")) == Some(GitStatus::Staged));
+        assert!(statuses.get(&root.join("b.txt")) == Some(GitStatus::Modified));
+        // The original paths aren't read as entries of their own
+        assert!(statuses.get(&root.join("old.txt")).is_none());
+        assert!(statuses.get(&root.join("orig.txt")).is_none());
+        assert_eq!(statuses.branch.unwrap().name, "main");
+    }
+
+    #[test]
+    fn directories_take_the_most_important_status() {
+        let root = PathBuf::from("/repo");
+        let output = b"?? src/new.rs\0UU src/lib.rs\0!! target/\0?? docs/\0";
+        let statuses = GitStatuses::parse(root.clone(), output);
+        assert!(statuses.get(&root.join("src")) == Some(GitStatus::Conflicted));
+        // Untracked and ignored directories pass their status down
+        assert!(statuses.get(&root.join("docs/guide.md")) == Some(GitStatus::Untracked));
+        assert!(statuses.get(&root.join("target/debug")) == Some(GitStatus::Ignored));
+        assert!(statuses.get(&root.join("src/..")).is_none());
+    }
+}
@@ -21,6 +21,12 @@ pub enum Operation {
         old_path: PathBuf,
         new_path: PathBuf,
     },
+    Copy {
+        source: PathBuf,
+        destination: PathBuf,
+    },
+    /// Operations done together and undone together, in order.
+    Batch(Vec<Operation>),
 }
 
 impl Operation {
@@ -40,11 +46,58 @@ impl Operation {
             Self::Rename { old_path, new_path, } => Self::Rename {
                 old_path: old_path.clone(),
                 new_path: new_path.clone(),
-            }
+            },
+            Self::Copy { source, destination } => Self::Copy {
+                source: source.clone(),
+                destination: destination.clone(),
+            },
+            Self::Batch(operations) => Self::Batch(operations.iter().map(Operation::clone).collect()),
         }
     }
 }
 
+/// Undo history of file operations, shared by all tabs.
+#[derive(Default)]
+pub struct History {
+    operations: Vec<Operation>,
+    index: usize,
+    generation: usize,
+}
+
+impl History {
+    /// Records a new operation, dropping the ones that were undone.
+    pub fn push(&mut self, operation: Operation) {
+        self.operations.truncate(self.index);
+        self.operations.push(operation);
+        self.index += 1;
+        self.generation += 1;
+    }
+
+    /// Changes every time files are touched through the history, so that
+    /// listings know when to reload.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// Steps back and returns the operation to revert, if any.
+    pub fn undo(&mut self) -> Option<Operation> {
+        if self.index == 0 {
+            return None;
+        }
+        self.index -= 1;
+        self.generation += 1;
+        Some(self.operations[self.index].clone())
+    }
+
+    /// Steps forward and returns the operation to apply again, if any.
+    pub fn redo(&mut self) -> Option<Operation> {
+        let operation = self.operations.get(self.index)?.clone();
+        self.index += 1;
+        self.generation += 1;
+        Some(operation)
+    }
+}
+
 #[derive(Clone)]
 pub struct DirBackup {
     pub files: HashMap<PathBuf, Vec<u8>>,
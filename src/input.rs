@@ -1,11 +1,14 @@
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use crossterm::event::{Event, KeyEvent, MouseEvent, KeyCode, KeyModifiers, MouseEventKind, MouseButton};
-use std::path::PathBuf;
 use crate::error::Result;
-use crate::state::AppState;
-use crate::modes::{Mode, ModeAction};
-use crate::file_ops;
+use crate::state::{AppState, Listing};
+use crate::modes::{ExplorerAction, Mode, ModeAction};
+use crate::file_ops::{self, Visibility};
+use crate::git;
 use crate::history::Operation;
+use crate::matcher::Matcher;
+use crate::sort::SortOrder;
 use crate::terminal;
 use crate::ui::Renderer;
 
@@ -15,17 +18,18 @@ impl InputHandler {
     pub fn handle_event<W: Write>(
         event: Event,
         state: &mut AppState,
-        renderer: &mut Renderer,
+        renderer: &Renderer,
         writer: &mut W,
-    ) -> Result<Option<PathBuf>> {
+    ) -> Result<Option<ExplorerAction>> {
         match event {
-            Event::Key(key_event) => Self::handle_key_event(key_event, state, renderer, writer),
+            Event::Key(key_event) => Self::handle_key_event(key_event, state, writer),
             Event::Mouse(mouse_event) => {
-                Self::handle_mouse_event(mouse_event, state, renderer)?;
+                Self::handle_mouse_event(mouse_event, state, renderer, writer)?;
                 Ok(None)
             },
             Event::Resize(_, _) => {
-                renderer.update_viewport(state.selected, state.entries.len());
+                state.viewport.resize(renderer.list_height());
+                state.viewport.update(state.selected, state.entries.len());
                 Ok(None)
             },
             _ => Ok(None),
@@ -35,30 +39,46 @@ impl InputHandler {
     fn handle_key_event<W: Write>(
         key_event: KeyEvent,
         state: &mut AppState,
-        renderer: &mut Renderer,
         writer: &mut W,
-    ) -> Result<Option<PathBuf>> {
+    ) -> Result<Option<ExplorerAction>> {
         // Reset delete mode unless pressing 'd' again
         if key_event.code != KeyCode::Char('d') {
             state.delete_mode = None;
         }
+        if key_event.code != KeyCode::Char('X') {
+            state.discard_mode = false;
+        }
+        state.message = None;
 
         if state.prompt.is_active() {
             Self::handle_prompt_input(key_event, state)
         } else {
-            Self::handle_normal_input(key_event, state, renderer, writer)
+            Self::handle_normal_input(key_event, state, writer)
         }
     }
 
     fn handle_prompt_input(
         key_event: KeyEvent,
         state: &mut AppState,
-    ) -> Result<Option<PathBuf>> {
+    ) -> Result<Option<ExplorerAction>> {
         match key_event.code {
             KeyCode::Esc => {
+                if *state.prompt.get_mode() == Mode::Filter {
+                    state.set_filter(None)?;
+                }
                 state.prompt.set_mode(Mode::Normal);
                 Ok(None)
             },
+            KeyCode::Tab if *state.prompt.get_mode() == Mode::Search => {
+                state.prompt.cycle_match_mode(&state.entries);
+                Ok(None)
+            },
+            KeyCode::Tab if *state.prompt.get_mode() == Mode::Filter => {
+                state.prompt.cycle_match_mode(&[]);
+                let filter = Matcher::new(state.prompt.match_mode(), state.prompt.get_query());
+                state.set_filter(Some(filter))?;
+                Ok(None)
+            },
             KeyCode::Enter | KeyCode::Char(_) | KeyCode::Backspace => {
                 let input = match key_event.code {
                     KeyCode::Enter => '\n',
@@ -68,13 +88,18 @@ impl InputHandler {
                 };
 
                 let selected_path = state.entries.get(state.selected);
-                if let Some(action) = state.prompt.handle_input(
+                let action = state.prompt.handle_input(
                     input,
                     &state.entries,
                     &state.current_path,
                     selected_path,
-                )? {
-                    Self::handle_mode_action(action, state)?;
+                    state.config.git_mv,
+                );
+                // A failed rename or creation leaves the prompt open to fix it
+                match action {
+                    Ok(Some(action)) => Self::handle_mode_action(action, state)?,
+                    Ok(None) => {}
+                    Err(err) => state.message = Some(err.to_string()),
                 }
                 Ok(None)
             },
@@ -85,9 +110,8 @@ impl InputHandler {
     fn handle_normal_input<W: Write>(
         key_event: KeyEvent,
         state: &mut AppState,
-        renderer: &mut Renderer,
         writer: &mut W,
-    ) -> Result<Option<PathBuf>> {
+    ) -> Result<Option<ExplorerAction>> {
         match key_event.code {
             KeyCode::Char('/') => {
                 state.prompt.set_mode(Mode::Search);
@@ -103,6 +127,70 @@ impl InputHandler {
                 state.prompt.set_mode(Mode::Create);
                 Ok(None)
             },
+            KeyCode::Char('f') => {
+                state.prompt.set_mode(Mode::Find);
+                Ok(None)
+            },
+            KeyCode::Char('|') => {
+                let query = state.filter.as_ref().map_or("", |filter| filter.query()).to_string();
+                state.prompt.set_mode_with_text(Mode::Filter, &query);
+                Ok(None)
+            },
+            KeyCode::Esc if state.filter.is_some() => {
+                state.set_filter(None)?;
+                Ok(None)
+            },
+            KeyCode::Char('S') => {
+                let sort = SortOrder { key: state.sort.key.next(), ..state.sort };
+                state.set_sort(sort)?;
+                Ok(None)
+            },
+            KeyCode::Char('Z') => {
+                let sort = SortOrder { reverse: !state.sort.reverse, ..state.sort };
+                state.set_sort(sort)?;
+                Ok(None)
+            },
+            KeyCode::Char('D') => {
+                let sort = SortOrder { dirs_first: !state.sort.dirs_first, ..state.sort };
+                state.set_sort(sort)?;
+                Ok(None)
+            },
+            KeyCode::Char('.') => {
+                let visibility = Visibility { show_hidden: !state.visibility.show_hidden, ..state.visibility };
+                state.set_visibility(visibility)?;
+                Ok(None)
+            },
+            KeyCode::Char('I') => {
+                let visibility = Visibility { hide_ignored: !state.visibility.hide_ignored, ..state.visibility };
+                state.set_visibility(visibility)?;
+                Ok(None)
+            },
+            KeyCode::Char('+') => {
+                Self::run_git(state, git::stage);
+                Ok(None)
+            },
+            KeyCode::Char('-') => {
+                Self::run_git(state, git::unstage);
+                Ok(None)
+            },
+            KeyCode::Char('X') => {
+                if state.discard_mode {
+                    state.discard_mode = false;
+                    Self::run_git(state, git::discard);
+                } else if !state.marked_or_selected().is_empty() {
+                    state.discard_mode = true;
+                    state.message = Some("Press X again to discard the unstaged changes".to_string());
+                }
+                Ok(None)
+            },
+            KeyCode::Char('F') => {
+                state.prompt.set_mode(Mode::Grep);
+                Ok(None)
+            },
+            KeyCode::Char('o') => {
+                state.reveal()?;
+                Ok(None)
+            },
             KeyCode::Char('r') if key_event.modifiers == KeyModifiers::CONTROL => {
                 Self::redo(state)?;
                 Ok(None)
@@ -119,26 +207,55 @@ impl InputHandler {
             },
             KeyCode::Char('q') => {
                 terminal::cleanup(writer);
-                Ok(Some(state.current_path.clone()))
+                Ok(Some(ExplorerAction::Quit))
+            },
+            KeyCode::Char('Q') => {
+                terminal::cleanup(writer);
+                Ok(Some(ExplorerAction::QuitAndCd(state.current_path.clone())))
+            },
+            KeyCode::Char('t') => Ok(Some(ExplorerAction::NewTab)),
+            KeyCode::Char('w') => Ok(Some(ExplorerAction::CloseTab)),
+            KeyCode::Tab => Ok(Some(ExplorerAction::NextTab)),
+            KeyCode::BackTab => Ok(Some(ExplorerAction::PreviousTab)),
+            KeyCode::Char(c @ '1'..='9') => {
+                Ok(Some(ExplorerAction::SwitchTab(c as usize - '1' as usize)))
+            },
+            KeyCode::Char('s') => Ok(Some(ExplorerAction::ToggleDualPane)),
+            KeyCode::Char('v') => Ok(Some(ExplorerAction::ToggleColumns)),
+            KeyCode::Char('T') => {
+                state.toggle_tree()?;
+                Ok(None)
+            },
+            KeyCode::Char('R') => {
+                state.toggle_recursive()?;
+                Ok(None)
+            },
+            KeyCode::Char('c') => Ok(Some(ExplorerAction::Transfer(Mode::Copy))),
+            KeyCode::Char('m') => Ok(Some(ExplorerAction::Transfer(Mode::Move))),
+            KeyCode::Char(' ') => {
+                state.toggle_mark();
+                Self::increment_selected(state);
+                state.viewport.update(state.selected, state.entries.len());
+                Ok(None)
             },
             KeyCode::Char('j') | KeyCode::Down => {
                 Self::increment_selected(state);
-                renderer.update_viewport(state.selected, state.entries.len());
+                state.viewport.update(state.selected, state.entries.len());
                 Ok(None)
             },
             KeyCode::Char('k') | KeyCode::Up => {
                 Self::decrement_selected(state);
-                renderer.update_viewport(state.selected, state.entries.len());
+                state.viewport.update(state.selected, state.entries.len());
                 Ok(None)
             },
             KeyCode::Char('G') | KeyCode::End => {
                 Self::goto_footer(state);
-                renderer.update_viewport(state.selected, state.entries.len());
+                state.viewport.update(state.selected, state.entries.len());
                 Ok(None)
             },
             KeyCode::Char('g') | KeyCode::Home => {
                 Self::goto_header(state);
-                renderer.update_viewport(state.selected, state.entries.len());
+                state.viewport.update(state.selected, state.entries.len());
                 Ok(None)
             },
             KeyCode::Char('d') => {
@@ -150,44 +267,50 @@ impl InputHandler {
                 Ok(None)
             },
             KeyCode::Enter | KeyCode::Right => {
-                Self::navigate(state, renderer)?;
+                Self::navigate(state, writer)?;
                 Ok(None)
             },
             KeyCode::Left | KeyCode::Char('b') | KeyCode::Backspace => {
-                Self::back(state);
+                Self::back(state)?;
                 Ok(None)
             }
             _ => Ok(None)
         }
     }
 
-    fn handle_mouse_event(
+    fn handle_mouse_event<W: Write>(
         event: MouseEvent,
         state: &mut AppState,
-        renderer: &mut Renderer,
+        renderer: &Renderer,
+        writer: &mut W,
     ) -> Result<()> {
         match event.kind {
             MouseEventKind::ScrollDown => {
-                renderer.scroll_down(state.entries.len());
+                state.viewport.scroll_down(state.entries.len());
                 // Adjust selection if it's above viewport
-                if state.selected < renderer.get_viewport_start() {
-                    state.selected = renderer.get_viewport_start();
+                if state.selected < state.viewport.start() {
+                    state.selected = state.viewport.start();
                 }
             }
             MouseEventKind::ScrollUp => {
-                renderer.scroll_up();
+                state.viewport.scroll_up();
                 // Adjust selection if it's below viewport
-                let viewport_end = renderer.get_viewport_start() + renderer.get_viewport_size();
+                let viewport_end = state.viewport.start() + state.viewport.size();
                 if state.selected >= viewport_end {
                     state.selected = viewport_end - 1;
                 }
             }
             MouseEventKind::Down(MouseButton::Left) => {
-                let viewport_relative_row = event.row as usize;
-                let absolute_row = renderer.get_viewport_start() + viewport_relative_row;
+                if let Some(index) = renderer.title_at(state, event.column, event.row) {
+                    return state.sort_by_column(index);
+                }
+                let Some(viewport_relative_row) = (event.row as usize).checked_sub(renderer.list_top()) else {
+                    return Ok(());
+                };
+                let absolute_row = state.viewport.start() + viewport_relative_row;
                 if absolute_row < state.entries.len() {
                     if state.selected == absolute_row {
-                        Self::navigate(state, renderer)?;
+                        Self::navigate(state, writer)?;
                     } else {
                         state.selected = absolute_row;
                     }
@@ -202,41 +325,65 @@ impl InputHandler {
         match action {
             ModeAction::Select(index) => {
                 state.selected = index;
+                state.recompute_display_data();
             },
             ModeAction::CreateEntry(operation) => {
-                state.history.push(operation);
-                state.history_index += 1;
-                state.entries = file_ops::read_dir_entries(&state.current_path)?;
-                state.selected = state.entries.len() - 1;
+                if let Operation::Create { path, .. } = &operation {
+                    state.path_created(path);
+                    state.refresh()?;
+                    state.select_path(path);
+                }
+                state.history.borrow_mut().push(operation);
             },
             ModeAction::RenameEntry(operation) => {
-                state.history.push(operation);
-                state.history_index += 1;
-                state.entries = file_ops::read_dir_entries(&state.current_path)?;
+                if let Operation::Rename { old_path, new_path } = &operation {
+                    state.path_renamed(old_path, new_path);
+                }
+                state.history.borrow_mut().push(operation);
+                state.refresh()?;
+            },
+            ModeAction::CopyTo(target) => Self::transfer(state, &target, false)?,
+            ModeAction::MoveTo(target) => Self::transfer(state, &target, true)?,
+            ModeAction::Find(query) => state.find(&query)?,
+            ModeAction::Grep(pattern) => state.grep(&pattern)?,
+            ModeAction::Filter(query) => {
+                let filter = (!query.is_empty()).then(|| Matcher::new(state.prompt.match_mode(), &query));
+                state.set_filter(filter)?;
             },
-            ModeAction::Exit => {},
+            ModeAction::Exit => state.recompute_display_data(),
         }
-        state.recompute_display_data();
         Ok(())
     }
 
-    fn navigate(state: &mut AppState, renderer: &mut Renderer) -> Result<()> {
+    fn navigate<W: Write>(state: &mut AppState, writer: &mut W) -> Result<()> {
         if state.selected < state.entries.len() {
-            let selected_path = &state.entries[state.selected];
-            if selected_path.is_dir() {
-                std::env::set_current_dir(selected_path)?;
-                state.current_path = std::env::current_dir()?;
-                state.entries = file_ops::read_dir_entries(&state.current_path)?;
-                state.selected = 1;
-                state.recompute_display_data();
-                renderer.reset_viewport();
+            let selected_path = state.entries[state.selected].clone();
+            if state.is_tree() && state.selected > 0 && selected_path.is_dir() {
+                state.toggle_expanded(&selected_path)?;
+            } else if selected_path.is_dir() {
+                state.change_dir(&selected_path)?;
+            } else if let Some(line) = &state.lines[state.selected] {
+                // The editor takes over the terminal until it exits
+                terminal::suspend(writer);
+                let result = file_ops::open_file_at_line(&selected_path, line.number);
+                terminal::resume(writer);
+                result?;
             } else {
-                file_ops::open_file_in_editor(selected_path)?;
+                file_ops::open_file_in_editor(&selected_path)?;
             }
         }
         Ok(())
     }
 
+    /// Runs a git action on the marked entries, or the selected one. A
+    /// failure is shown rather than returned, as git explains it.
+    fn run_git(state: &mut AppState, action: fn(&[PathBuf]) -> Result<()>) {
+        let paths = state.marked_or_selected();
+        if let Err(err) = action(&paths).and_then(|_| state.refresh()) {
+            state.message = Some(err.to_string());
+        }
+    }
+
     fn handle_delete(state: &mut AppState) -> Result<()> {
         if state.selected > 0 && state.selected < state.entries.len() {
             let selected_path = &state.entries[state.selected];
@@ -250,66 +397,131 @@ impl InputHandler {
             
             file_ops::delete_path(selected_path, selected_path.is_dir())?;
             
-            if state.history_index < state.history.len() {
-                state.history.truncate(state.history_index);
-            }
-            
-            state.history.push(operation);
-            state.history_index += 1;
-            state.entries.remove(state.selected);
+            state.history.borrow_mut().push(operation);
             state.delete_mode = None;
-            state.recompute_display_data();
+            // The next entry takes the place of the deleted one
+            state.refresh()?;
         }
         Ok(())
     }
 
-    fn undo(state: &mut AppState) -> Result<()> {
-        if state.history_index > 0 {
-            state.history_index -= 1;
-            let operation = &state.history[state.history_index];
+    /// Copies or moves the marked entries, or the selected one, to `target`.
+    /// Failures are shown on the status line.
+    fn transfer(state: &mut AppState, target: &Path, is_move: bool) -> Result<()> {
+        let sources = state.marked_or_selected();
+        if sources.len() > 1 && !target.is_dir() {
+            state.message = Some(format!("{} is not a directory", target.display()));
+            return Ok(());
+        }
 
-            match operation {
-                Operation::Delete { path, is_dir, content, dir_backup, .. } => {
-                    file_ops::restore_deleted_path(path, *is_dir, content, dir_backup)?;
-                    state.entries = file_ops::read_dir_entries(&state.current_path)?;
-                },
-                Operation::Create { path, is_dir } => {
-                    file_ops::delete_path(path, *is_dir)?;
-                    state.entries = file_ops::read_dir_entries(&state.current_path)?;
-                },
-                Operation::Rename { old_path, new_path } => {
-                    file_ops::rename_path(new_path, old_path)?;
-                    state.entries = file_ops::read_dir_entries(&state.current_path)?;
+        let mut operations = Vec::new();
+        let mut result = Ok(());
+        for source in sources {
+            result = file_ops::transfer_destination(&source, target).and_then(|destination| {
+                if is_move {
+                    Self::rename(state, &source, &destination)?;
+                    state.path_renamed(&source, &destination);
+                    operations.push(Operation::Rename { old_path: source, new_path: destination });
+                } else {
+                    file_ops::copy_path(&source, &destination)?;
+                    operations.push(Operation::Copy { source, destination });
                 }
+                Ok(())
+            });
+            if result.is_err() {
+                break;
             }
-            state.recompute_display_data();
+        }
+
+        // Record what was done even if a later entry failed, so it can be undone
+        match operations.len() {
+            0 => {},
+            1 => state.history.borrow_mut().push(operations.remove(0)),
+            _ => state.history.borrow_mut().push(Operation::Batch(operations)),
+        }
+        state.marked.clear();
+        state.refresh()?;
+        if let Err(err) = result {
+            state.message = Some(err.to_string());
+        }
+        Ok(())
+    }
+
+    fn undo(state: &mut AppState) -> Result<()> {
+        let operation = state.history.borrow_mut().undo();
+        if let Some(operation) = &operation {
+            Self::revert_operation(state, operation)?;
+            state.refresh()?;
         }
         Ok(())
     }
 
     fn redo(state: &mut AppState) -> Result<()> {
-        if state.history_index < state.history.len() {
-            let operation = &state.history[state.history_index].clone();
+        let operation = state.history.borrow_mut().redo();
+        if let Some(operation) = &operation {
+            Self::apply_operation(state, operation)?;
+            state.refresh()?;
+        }
+        Ok(())
+    }
 
-            match operation {
-                Operation::Delete { path, is_dir, .. } => {
-                    file_ops::delete_path(path, *is_dir)?;
-                },
-                Operation::Create { path, is_dir } => {
-                    if *is_dir {
-                        file_ops::create_directory(path)?;
-                    } else {
-                        file_ops::create_file(path)?;
-                    }
-                },
-                Operation::Rename { old_path, new_path } => {
-                    file_ops::rename_path(old_path, new_path)?;
+    /// Renames with `git mv` when the configuration asks for it.
+    fn rename(state: &AppState, old_path: &Path, new_path: &Path) -> Result<()> {
+        if state.config.git_mv {
+            git::rename(old_path, new_path)
+        } else {
+            file_ops::rename_path(old_path, new_path)
+        }
+    }
+
+    fn revert_operation(state: &mut AppState, operation: &Operation) -> Result<()> {
+        match operation {
+            Operation::Delete { path, is_dir, content, dir_backup, .. } => {
+                file_ops::restore_deleted_path(path, *is_dir, content, dir_backup)?;
+            },
+            Operation::Create { path, is_dir } => {
+                file_ops::delete_path(path, *is_dir)?;
+            },
+            Operation::Rename { old_path, new_path } => {
+                Self::rename(state, new_path, old_path)?;
+                state.path_renamed(new_path, old_path);
+            },
+            Operation::Copy { destination, .. } => {
+                file_ops::delete_path(destination, destination.is_dir())?;
+            },
+            Operation::Batch(operations) => {
+                for operation in operations.iter().rev() {
+                    Self::revert_operation(state, operation)?;
                 }
             }
+        }
+        Ok(())
+    }
 
-            state.history_index += 1;
-            state.entries = file_ops::read_dir_entries(&state.current_path)?;
-            state.recompute_display_data();
+    fn apply_operation(state: &mut AppState, operation: &Operation) -> Result<()> {
+        match operation {
+            Operation::Delete { path, is_dir, .. } => {
+                file_ops::delete_path(path, *is_dir)?;
+            },
+            Operation::Create { path, is_dir } => {
+                if *is_dir {
+                    file_ops::create_directory(path)?;
+                } else {
+                    file_ops::create_file(path)?;
+                }
+            },
+            Operation::Rename { old_path, new_path } => {
+                Self::rename(state, old_path, new_path)?;
+                state.path_renamed(old_path, new_path);
+            },
+            Operation::Copy { source, destination } => {
+                file_ops::copy_path(source, destination)?;
+            },
+            Operation::Batch(operations) => {
+                for operation in operations {
+                    Self::apply_operation(state, operation)?;
+                }
+            }
         }
         Ok(())
     }
@@ -338,14 +550,27 @@ impl InputHandler {
         }
     }
 
-    fn back(state: &mut AppState) {
-        let parent = state.current_path.parent();
-        if let Some(parent) = parent {
-            std::env::set_current_dir(parent).unwrap();
-            state.current_path = std::env::current_dir().unwrap();
-            state.entries = file_ops::read_dir_entries(&state.current_path).unwrap();
-            state.selected = 1;
-            state.recompute_display_data();
+    fn back(state: &mut AppState) -> Result<()> {
+        // In a tree, collapse the selected directory or go up to its parent row
+        let selected_path = state.entries[state.selected].clone();
+        if state.is_expanded(&selected_path) {
+            return state.toggle_expanded(&selected_path);
+        }
+        if state.depths[state.selected] > 0 {
+            if let Some(parent) = selected_path.parent() {
+                state.select_path(parent);
+            }
+            return Ok(());
+        }
+
+        // Leaving a path listing goes back to the directory it was opened from
+        let target = match state.listing {
+            Listing::Directory | Listing::Tree(_) | Listing::Recursive => state.current_path.parent().map(Path::to_path_buf),
+            Listing::Paths(_) | Listing::Results(_) => Some(state.current_path.clone()),
+        };
+        match target {
+            Some(target) => state.change_dir(&target),
+            None => Ok(()),
         }
     }
 }
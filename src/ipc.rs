@@ -0,0 +1,157 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use crate::error::{ExplorerError, Result};
+
+/// A command sent to a running instance over its socket.
+///
+/// The protocol is line based: each request is a single line such as
+/// `cd /tmp` or `get selection`, answered by a line starting with `ok` or
+/// `error`, followed by the value or message if any.
+pub enum Request {
+    Cd(PathBuf),
+    Select(PathBuf),
+    Refresh,
+    GetCwd,
+    GetSelection,
+}
+
+pub enum Response {
+    Ok,
+    Value(String),
+    Error(String),
+}
+
+impl Request {
+    pub fn parse(line: &str) -> std::result::Result<Self, String> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (command, arg) = match line.split_once(' ') {
+            Some((command, arg)) => (command, arg.trim()),
+            None => (line, ""),
+        };
+        match (command, arg) {
+            ("cd", "") | ("select", "") => Err(format!("{} expects a path", command)),
+            ("cd", path) => Ok(Request::Cd(PathBuf::from(path))),
+            ("select", path) => Ok(Request::Select(PathBuf::from(path))),
+            ("refresh", "") => Ok(Request::Refresh),
+            ("get", "cwd") => Ok(Request::GetCwd),
+            ("get", "selection") => Ok(Request::GetSelection),
+            ("get", what) => Err(format!("unknown property: {}", what)),
+            _ => Err(format!("unknown command: {}", line)),
+        }
+    }
+
+    /// Makes relative paths absolute against `base`, so that a client can
+    /// send paths relative to its own working directory.
+    pub fn resolve(self, base: &Path) -> Self {
+        match self {
+            Request::Cd(path) => Request::Cd(base.join(path)),
+            Request::Select(path) => Request::Select(base.join(path)),
+            request => request,
+        }
+    }
+
+    fn to_line(&self) -> String {
+        match self {
+            Request::Cd(path) => format!("cd {}", path.display()),
+            Request::Select(path) => format!("select {}", path.display()),
+            Request::Refresh => "refresh".to_string(),
+            Request::GetCwd => "get cwd".to_string(),
+            Request::GetSelection => "get selection".to_string(),
+        }
+    }
+}
+
+impl Response {
+    fn to_line(&self) -> String {
+        match self {
+            Response::Ok => "ok".to_string(),
+            Response::Value(value) => format!("ok {}", value),
+            Response::Error(msg) => format!("error {}", msg),
+        }
+    }
+}
+
+/// Accepts connections on a per-instance socket and forwards the requests
+/// to the event loop, which answers through the paired sender.
+pub struct IpcServer {
+    path: PathBuf,
+    receiver: Receiver<(Request, Sender<Response>)>,
+}
+
+impl IpcServer {
+    /// Socket of this instance, in the runtime directory.
+    pub fn socket_path() -> PathBuf {
+        let dir = dirs::runtime_dir().unwrap_or_else(std::env::temp_dir);
+        dir.join(format!("rx-{}.sock", std::process::id()))
+    }
+
+    /// Listens on `path`, serving the connections from a thread.
+    pub fn start(path: PathBuf) -> Result<Self> {
+        // A stale socket from a previous process with the same pid
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let sender = sender.clone();
+                thread::spawn(move || Self::serve(stream, sender));
+            }
+        });
+
+        Ok(Self { path, receiver })
+    }
+
+    pub fn try_recv(&self) -> Option<(Request, Sender<Response>)> {
+        self.receiver.try_recv().ok()
+    }
+
+    fn serve(stream: UnixStream, sender: Sender<(Request, Sender<Response>)>) {
+        let Ok(mut writer) = stream.try_clone() else { return };
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else { return };
+            let response = match Request::parse(&line) {
+                Ok(request) => {
+                    let (reply, answer) = mpsc::channel();
+                    if sender.send((request, reply)).is_err() {
+                        return;
+                    }
+                    answer.recv().unwrap_or(Response::Error("explorer closed".to_string()))
+                }
+                Err(msg) => Response::Error(msg),
+            };
+            if writeln!(writer, "{}", response.to_line()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Sends a single request to the instance listening on `socket` and returns
+/// the value it answered with, if any.
+pub fn send_request(socket: &Path, request: &Request) -> Result<Option<String>> {
+    let mut stream = UnixStream::connect(socket).map_err(|e| {
+        ExplorerError::OperationFailed(format!("Failed to connect to {}: {}", socket.display(), e))
+    })?;
+    writeln!(stream, "{}", request.to_line())?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let line = line.trim_end_matches(['\r', '\n']);
+    match line.split_once(' ').unwrap_or((line, "")) {
+        ("ok", "") => Ok(None),
+        ("ok", value) => Ok(Some(value.to_string())),
+        ("error", msg) => Err(ExplorerError::OperationFailed(msg.to_string())),
+        _ => Err(ExplorerError::OperationFailed(format!("Unexpected response: {}", line))),
+    }
+}
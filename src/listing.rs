@@ -0,0 +1,131 @@
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::time::SystemTime;
+use crossterm::style::{ContentStyle, StyledContent};
+
+use crate::config::Config;
+use crate::error::{ExplorerError, Result};
+use crate::explorer::FileExplorer;
+use crate::file_ops;
+use crate::git::GitStatus;
+use crate::lua::{Column, Entry};
+use crate::state::{AppState, Listing};
+use crate::styled::{Span, SpanStyle, StyledText};
+use crate::theme::Theme;
+
+/// Prints a directory listing to stdout using the configured display
+/// modules, aligned the same way as in the explorer.
+///
+/// When `path` is a file, only that file's row is printed. With `json`, the
+/// rows are printed as a JSON array of entries instead.
+pub fn print_listing(config: Config, path: &Path, color: clap::ColorChoice, json: bool) -> Result<()> {
+    let (dir, file) = file_ops::resolve_start_path(path)?;
+    let plugins = FileExplorer::load_plugins(&config)?;
+    let theme = plugins.theme;
+    let state = AppState::new(config, plugins.display_modules, plugins.hooks, &dir, Listing::Directory)?;
+
+    let rows = (1..state.entries.len())
+        .filter(|&i| file.as_ref().is_none_or(|file| *file == state.entries[i]));
+
+    if json {
+        let entries: Vec<JsonEntry> = rows
+            .map(|i| JsonEntry::new(state.entry_at(i), &state.modules_cache[i]))
+            .collect();
+        let mut out = io::stdout().lock();
+        serde_json::to_writer_pretty(&mut out, &entries)
+            .map_err(|e| ExplorerError::OperationFailed(format!("Failed to write JSON: {}", e)))?;
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    let use_color = match color {
+        clap::ColorChoice::Always => true,
+        clap::ColorChoice::Never => false,
+        clap::ColorChoice::Auto => io::stdout().is_terminal(),
+    };
+
+    let mut out = io::stdout().lock();
+    if state.has_titles() {
+        let titles: Vec<StyledText> = state.display_modules
+            .iter()
+            .map(|column| {
+                let text = column.title.clone().unwrap_or_default();
+                StyledText::new(vec![Span { text, style: SpanStyle { bold: true, ..SpanStyle::default() } }])
+            })
+            .collect();
+        writeln!(out, "{}", format_row(&titles, &state.display_modules, &state.max_widths, use_color, &theme))?;
+    }
+    for i in rows {
+        let line = format_row(&state.modules_cache[i], &state.display_modules, &state.max_widths, use_color, &theme);
+        if let Err(e) = writeln!(out, "{}", line) {
+            // The reader went away (e.g. `rx ls | head`), nothing left to do
+            if e.kind() == io::ErrorKind::BrokenPipe {
+                return Ok(());
+            }
+            return Err(e.into());
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn format_row(modules: &[StyledText], columns: &[Column], max_widths: &[usize], use_color: bool, theme: &Theme) -> String {
+    let mut line = String::new();
+    for ((module, column), &width) in modules.iter().zip(columns).zip(max_widths) {
+        let module = module.truncate(width);
+        let (before, after) = column.align.padding(width - module.width());
+        line.push_str(&" ".repeat(before));
+        if use_color && !module.is_blank() {
+            let mut base = ContentStyle::new();
+            base.foreground_color = Some(theme.fg);
+            for span in &module.spans {
+                let style = span.style.apply(base, true);
+                line.push_str(&StyledContent::new(style, span.text.as_str()).to_string());
+            }
+        } else {
+            line.push_str(&module.text());
+        }
+        line.push_str(&" ".repeat(after + 1));
+    }
+    line.trim_end().to_string()
+}
+
+/// An entry as printed by `rx ls --json`. Times are seconds since the epoch,
+/// like the fields exposed to Lua.
+#[derive(serde::Serialize)]
+struct JsonEntry {
+    path: String,
+    name: String,
+    is_dir: bool,
+    is_symlink: bool,
+    created: u64,
+    modified: u64,
+    size: u64,
+    mode: u32,
+    /// Git status name, or null outside a repository and for clean files
+    git: Option<&'static str>,
+    modules: Vec<String>,
+}
+
+impl JsonEntry {
+    fn new(entry: Entry, modules: &[StyledText]) -> Self {
+        Self {
+            path: entry.path.to_string_lossy().to_string(),
+            name: entry.name,
+            is_dir: entry.is_dir,
+            is_symlink: entry.is_symlink,
+            created: unix_seconds(entry.created),
+            modified: unix_seconds(entry.modified),
+            size: entry.size,
+            mode: entry.mode,
+            git: entry.git.map(GitStatus::name),
+            modules: modules.iter().map(StyledText::text).collect(),
+        }
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
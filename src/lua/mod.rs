@@ -1,7 +1,12 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::time::SystemTime;
 use mlua::prelude::*;
+use crate::git::GitStatus;
 use crate::icons;
+use crate::sort::{natural_cmp, SortKey};
+use crate::styled::{Span, SpanStyle, StyledText};
 
 #[derive(Clone)]
 pub struct Entry {
@@ -9,19 +14,187 @@ pub struct Entry {
     pub name: String,
     pub is_dir: bool,
     pub created: SystemTime,
+    pub modified: SystemTime,
     pub size: u64,
+    pub is_symlink: bool,
+    pub mode: u32,
+    /// `None` for unchanged entries and outside of repositories
+    pub git: Option<GitStatus>,
 }
 
-pub type DisplayModuleFn = Box<dyn Fn(&Entry) -> String + 'static>;
+pub type DisplayModuleFn = Box<dyn Fn(&Entry) -> StyledText + 'static>;
+
+/// What a column is sorted by. Numbers come before text.
+pub enum SortValue {
+    Number(f64),
+    Text(String),
+}
+
+impl SortValue {
+    pub fn compare(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (SortValue::Number(a), SortValue::Number(b)) => a.total_cmp(b),
+            (SortValue::Text(a), SortValue::Text(b)) => natural_cmp(&a.to_lowercase(), &b.to_lowercase()),
+            (SortValue::Number(_), SortValue::Text(_)) => Ordering::Less,
+            (SortValue::Text(_), SortValue::Number(_)) => Ordering::Greater,
+        }
+    }
+}
+
+impl FromLua for SortValue {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::Nil => Ok(SortValue::Text(String::new())),
+            LuaValue::Integer(number) => Ok(SortValue::Number(number as f64)),
+            LuaValue::Number(number) => Ok(SortValue::Number(number)),
+            value => Ok(SortValue::Text(String::from_lua(value, lua)?)),
+        }
+    }
+}
+
+pub type SortKeyFn = Box<dyn Fn(&Entry) -> SortValue + 'static>;
+
+/// How a column sorts the listing when its title is clicked.
+#[derive(Default)]
+pub enum ColumnSort {
+    /// By the text the module shows
+    #[default]
+    Output,
+    /// By the value of a key function
+    Custom(SortKeyFn),
+    /// By one of the built-in orders, for the built-in modules
+    Key(SortKey),
+}
+
+/// Where the output of a module goes in a wider column.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Align {
+    #[default]
+    Left,
+    Right,
+    Center,
+}
+
+impl Align {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "left" => Some(Align::Left),
+            "right" => Some(Align::Right),
+            "center" => Some(Align::Center),
+            _ => None,
+        }
+    }
+
+    /// Splits the `space` left in a column into the padding before and
+    /// after its text.
+    pub fn padding(self, space: usize) -> (usize, usize) {
+        match self {
+            Align::Left => (0, space),
+            Align::Right => (space, 0),
+            Align::Center => (space / 2, space - space / 2),
+        }
+    }
+}
+
+/// A display module and the layout of its column.
+pub struct Column {
+    pub module: DisplayModuleFn,
+    pub align: Align,
+    pub min_width: usize,
+    /// Longer output is cut with an ellipsis
+    pub max_width: Option<usize>,
+    /// Shown in the row of titles above the listing
+    pub title: Option<String>,
+    /// Columns with a priority are dropped, lowest first, when the listing
+    /// doesn't fit. Columns without one are always shown.
+    pub priority: Option<i64>,
+    pub sort: ColumnSort,
+}
+
+impl Column {
+    pub fn new(module: DisplayModuleFn) -> Self {
+        Self {
+            module,
+            align: Align::default(),
+            min_width: 0,
+            max_width: None,
+            title: None,
+            priority: None,
+            sort: ColumnSort::default(),
+        }
+    }
+
+    /// Value the column sorts `entry` by, unless it uses a built-in order.
+    pub fn sort_value(&self, entry: &Entry) -> Option<SortValue> {
+        match &self.sort {
+            ColumnSort::Output => Some(SortValue::Text((self.module)(entry).text().trim().to_string())),
+            ColumnSort::Custom(key) => Some(key(entry)),
+            ColumnSort::Key(_) => None,
+        }
+    }
+
+    /// Width of the column for output taking up to `content` columns.
+    pub fn width(&self, content: usize) -> usize {
+        let title = self.title.as_deref().map_or(0, unicode_width::UnicodeWidthStr::width);
+        let width = content.max(title);
+        self.max_width.map_or(width, |max| width.min(max)).max(self.min_width)
+    }
+}
+/// Whether the first entry goes before the second one
+pub type SorterFn = Box<dyn Fn(&Entry, &Entry) -> LuaResult<bool> + 'static>;
+/// Whether the entry is listed
+pub type FilterFn = Box<dyn Fn(&Entry) -> LuaResult<bool> + 'static>;
+
+/// What the status line and header are drawn for.
+pub struct LineContext {
+    pub path: PathBuf,
+    /// The selected entry
+    pub entry: Entry,
+    /// The marked paths
+    pub selection: Vec<PathBuf>,
+    pub mode: &'static str,
+    pub listing: &'static str,
+}
+
+/// Text of a part of the status line or header, or of a status bar segment
+pub type LineFn = Box<dyn Fn(&LineContext) -> String + 'static>;
+
+/// Functions set from Lua to shape the listings, run once per load, and the
+/// lines around them, run when the selection moves.
+#[derive(Default)]
+pub struct ListingHooks {
+    pub sorter: Option<SorterFn>,
+    pub filter: Option<FilterFn>,
+    pub status_line: Vec<LineFn>,
+    pub header: Vec<LineFn>,
+    /// Status bar segments added with `rx.addStatusSegment`, by name
+    pub status_segments: HashMap<String, LineFn>,
+}
 
 pub fn get_icon(entry: &Entry) -> String {
-    return icons::get_file_icon(entry.name.as_str()).to_string();
+    // The name of a search result also holds the matching line
+    let file_name = match entry.path.file_name() {
+        Some(file_name) if !entry.is_dir => file_name.to_string_lossy(),
+        _ => entry.name.as_str().into(),
+    };
+    return icons::get_file_icon(&file_name).to_string();
 }
 
 pub fn get_name(entry: &Entry) -> String {
     return entry.name.clone();
 }
 
+/// The status letter in bold, colored like the rest of the row.
+pub fn get_git_status(entry: &Entry) -> StyledText {
+    let Some(status) = entry.git else {
+        return StyledText::default();
+    };
+    StyledText::new(vec![Span {
+        text: status.symbol().to_string(),
+        style: SpanStyle { bold: true, ..SpanStyle::default() },
+    }])
+}
+
 pub fn get_creation_date(entry: &Entry) -> String {
     let datetime = entry.created
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -31,22 +204,27 @@ pub fn get_creation_date(entry: &Entry) -> String {
     time.format("%a %b %e %H:%M:%S %Y").to_string()
 }
 
-pub fn get_size(entry: &Entry) -> String {
+/// The size with its unit dimmed, empty for directories.
+pub fn get_size(entry: &Entry) -> StyledText {
     if entry.is_dir {
-        return "".to_string();
+        return StyledText::default();
     }
     let size = entry.size;
-    if size < 1024 {
-        format!("{:>3}  B", size)
+    let (value, unit) = if size < 1024 {
+        (size, "B")
     } else if size < 1024 * 1024 {
-        format!("{:>3.2} KB", (size as f64 / 1024.0) as u64)
+        ((size as f64 / 1024.0) as u64, "KB")
     } else if size < 1024 * 1024 * 1024 {
-        format!("{:>3.2} MB", (size as f64 / (1024.0 * 1024.0)) as u64)
+        ((size as f64 / (1024.0 * 1024.0)) as u64, "MB")
     } else if size < 1024 * 1024 * 1024 * 1024 {
-        format!("{:>3.2} GB", (size as f64 / (1024.0 * 1024.0 * 1024.0)) as u64)
+        ((size as f64 / (1024.0 * 1024.0 * 1024.0)) as u64, "GB")
     } else {
-        format!("{:>3.2} TB", (size as f64 / (1024.0 * 1024.0 * 1024.0 * 1024.0)) as u64)
-    }
+        ((size as f64 / (1024.0 * 1024.0 * 1024.0 * 1024.0)) as u64, "TB")
+    };
+    StyledText::new(vec![
+        Span { text: format!("{:>3} ", value), style: SpanStyle::default() },
+        Span { text: format!("{:>2}", unit), style: SpanStyle { dim: true, ..SpanStyle::default() } },
+    ])
 }
 
 fn get_spacer(size: usize) -> String {
@@ -84,6 +262,11 @@ pub fn create_rx_module<'lua>(lua: &'lua Lua) -> LuaResult<LuaTable> {
         Ok(get_name(&entry))
     })?)?;
     
+    rx_table.set("GitStatus", lua.create_function(|_, entry: LuaAnyUserData| {
+        let entry = entry.borrow::<Entry>()?;
+        Ok(get_git_status(&entry))
+    })?)?;
+
     rx_table.set("CreationDate", lua.create_function(|_, entry: LuaAnyUserData| {
         let entry = entry.borrow::<Entry>()?;
         Ok(get_creation_date(&entry))
@@ -115,7 +298,7 @@ pub fn create_rx_module<'lua>(lua: &'lua Lua) -> LuaResult<LuaTable> {
             let tbl = lua_ctx.create_table()?;
             for (i, module) in modules.into_iter().enumerate() {
                 match module {
-                    LuaValue::Function(f) => tbl.set(i + 1, f)?,
+                    LuaValue::Function(_) | LuaValue::Table(_) => tbl.set(i + 1, module)?,
                     _ => return Err(LuaError::RuntimeError("Invalid module type".into())),
                 }
             }
@@ -124,6 +307,57 @@ pub fn create_rx_module<'lua>(lua: &'lua Lua) -> LuaResult<LuaTable> {
         }
     })?)?;
 
+    rx_table.set("setColumnModules", lua.create_function({
+        let rx_table = rx_table.clone();
+        move |lua_ctx, modules: LuaMultiValue| {
+            let tbl = lua_ctx.create_table()?;
+            for (i, module) in modules.into_iter().enumerate() {
+                match module {
+                    LuaValue::Function(_) | LuaValue::Table(_) => tbl.set(i + 1, module)?,
+                    _ => return Err(LuaError::RuntimeError("Invalid module type".into())),
+                }
+            }
+            rx_table.set("column_modules", tbl)?;
+            Ok(())
+        }
+    })?)?;
+
+    rx_table.set("addStatusSegment", lua.create_function({
+        let rx_table = rx_table.clone();
+        move |lua_ctx, (name, segment): (String, LuaFunction)| {
+            let segments = match rx_table.get::<Option<LuaTable>>("status_segments")? {
+                Some(segments) => segments,
+                None => lua_ctx.create_table()?,
+            };
+            segments.set(name, segment)?;
+            rx_table.set("status_segments", segments)
+        }
+    })?)?;
+
+    rx_table.set("setStatusLine", lua.create_function({
+        let rx_table = rx_table.clone();
+        move |lua_ctx, parts: mlua::Variadic<LuaFunction>| {
+            rx_table.set("status_line", lua_ctx.create_sequence_from(parts)?)
+        }
+    })?)?;
+
+    rx_table.set("setHeader", lua.create_function({
+        let rx_table = rx_table.clone();
+        move |lua_ctx, parts: mlua::Variadic<LuaFunction>| {
+            rx_table.set("header", lua_ctx.create_sequence_from(parts)?)
+        }
+    })?)?;
+
+    rx_table.set("setSorter", lua.create_function({
+        let rx_table = rx_table.clone();
+        move |_, sorter: LuaFunction| rx_table.set("sorter", sorter)
+    })?)?;
+
+    rx_table.set("setFilter", lua.create_function({
+        let rx_table = rx_table.clone();
+        move |_, filter: LuaFunction| rx_table.set("filter", filter)
+    })?)?;
+
     let rx_clone = rx_table.clone();
     let f = lua.create_function(move |_, theme_tbl: LuaTable| {
         rx_clone.set("theme", theme_tbl)?;
@@ -135,17 +369,33 @@ pub fn create_rx_module<'lua>(lua: &'lua Lua) -> LuaResult<LuaTable> {
     Ok(rx_table)
 }
 
-pub fn default_display_modules(use_nerd_fonts: bool) -> Vec<DisplayModuleFn> {
+/// Wraps a module showing plain text.
+fn plain(module: fn(&Entry) -> String) -> DisplayModuleFn {
+    Box::new(move |entry| module(entry).into())
+}
+
+pub fn default_display_modules(use_nerd_fonts: bool) -> Vec<Column> {
     let mut display_modules: Vec<DisplayModuleFn> = Vec::new();
     if use_nerd_fonts {
-        display_modules.push(Box::new(get_icon));
+        display_modules.push(plain(get_icon));
     }
-    display_modules.push(Box::new(get_name));
-    display_modules.push(Box::new(get_small_spacer));
-    display_modules.push(Box::new(get_creation_date));
+    display_modules.push(plain(get_name));
+    display_modules.push(Box::new(get_git_status));
+    display_modules.push(plain(get_small_spacer));
+    display_modules.push(plain(get_creation_date));
     display_modules.push(Box::new(get_size));
-    display_modules.push(Box::new(get_small_spacer));
-    display_modules
+    display_modules.push(plain(get_small_spacer));
+    display_modules.into_iter().map(Column::new).collect()
+}
+
+/// Modules of the parent and child columns of the columns view.
+pub fn default_column_modules(use_nerd_fonts: bool) -> Vec<Column> {
+    let mut display_modules: Vec<DisplayModuleFn> = Vec::new();
+    if use_nerd_fonts {
+        display_modules.push(plain(get_icon));
+    }
+    display_modules.push(plain(get_name));
+    display_modules.into_iter().map(Column::new).collect()
 }
 
 impl LuaUserData for Entry {
@@ -160,7 +410,54 @@ impl LuaUserData for Entry {
                 .as_secs();
             Ok(datetime)
         });
+        fields.add_field_method_get("modified", |_, this| {
+            let datetime = this.modified
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            Ok(datetime)
+        });
         fields.add_field_method_get("size", |_, this| Ok(this.size));
+        fields.add_field_method_get("is_symlink", |_, this| Ok(this.is_symlink));
+        fields.add_field_method_get("mode", |_, this| Ok(this.mode));
+        fields.add_field_method_get("git", |_, this| Ok(this.git.map(GitStatus::name)));
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(min_width: usize, max_width: Option<usize>, title: Option<&str>) -> Column {
+        Column {
+            min_width,
+            max_width,
+            title: title.map(String::from),
+            ..Column::new(Box::new(|_| StyledText::default()))
+        }
+    }
+
+    #[test]
+    fn width_fits_the_content_within_the_bounds() {
+        let column = column(3, Some(8), None);
+        assert_eq!(column.width(5), 5);
+        assert_eq!(column.width(1), 3);
+        assert_eq!(column.width(20), 8);
+    }
+
+    #[test]
+    fn min_width_wins_over_max_width() {
+        let column = column(6, Some(4), None);
+        assert_eq!(column.width(2), 6);
+        assert_eq!(column.width(10), 6);
+    }
+
+    #[test]
+    fn titles_widen_the_column_up_to_max_width() {
+        assert_eq!(column(0, None, Some("Modified")).width(3), 8);
+        assert_eq!(column(0, Some(5), Some("Modified")).width(3), 5);
+        assert_eq!(column(0, None, Some("大小")).width(1), 4);
+        assert_eq!(column(0, None, Some("Size")).width(6), 6);
+    }
+}
@@ -1,5 +1,8 @@
-use std::io::{stdout, Write};
-use clap::Parser;
+use std::io::{self, stdout, BufRead, Write};
+use std::os::fd::AsFd;
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+use clap::{Parser, Subcommand};
 
 mod explorer;
 mod terminal;
@@ -15,27 +18,140 @@ mod theme;
 mod ui;
 mod input;
 mod state;
+mod shell;
+mod listing;
+mod ipc;
+mod find;
+mod matcher;
+mod sort;
+mod git;
+mod styled;
 
 use explorer::FileExplorer;
-use error::Result;
+use state::Listing;
+use error::{ExplorerError, Result};
 
 #[derive(Parser)]
 #[command(author, version, about)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Print the current directory to stdout when leaving with "quit and cd"
     #[arg(short = 'o', long)]
     out: bool,
+
+    /// Write the current directory to this file when leaving with "quit and cd"
+    #[arg(long, value_name = "PATH")]
+    cwd_file: Option<PathBuf>,
+
+    /// Directory to open, or a file to select in its parent directory
+    path: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print a shell function that changes directory when leaving rx
+    Init {
+        shell: shell::Shell,
+    },
+    /// Print a listing of PATH with the configured display modules
+    Ls {
+        path: Option<PathBuf>,
+
+        /// When to color the output
+        #[arg(long, value_name = "WHEN", default_value = "auto")]
+        color: clap::ColorChoice,
+
+        /// Print the entries and their display modules as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Send a command to a running instance: cd PATH, select PATH, refresh,
+    /// get cwd, get selection
+    Msg {
+        #[arg(required = true, num_args = 1..)]
+        command: Vec<String>,
+
+        /// Socket of the instance, defaults to $RX_SOCKET
+        #[arg(long, value_name = "PATH", env = "RX_SOCKET")]
+        socket: PathBuf,
+    },
+}
+
+
+fn main() {
+    if let Err(e) = run(Args::parse()) {
+        eprintln!("rx: {}", e);
+        std::process::exit(1);
+    }
 }
 
+fn run(args: Args) -> Result<()> {
+    match args.command {
+        Some(Command::Init { shell }) => {
+            print!("{}", shell::init_script(shell));
+            return Ok(());
+        }
+        Some(Command::Ls { path, color, json }) => {
+            let path = path.unwrap_or(std::env::current_dir()?);
+            return listing::print_listing(config::get_config(), &path, color, json);
+        }
+        Some(Command::Msg { command, socket }) => {
+            let request = ipc::Request::parse(&command.join(" "))
+                .map_err(ExplorerError::Other)?
+                .resolve(&std::env::current_dir()?);
+            if let Some(value) = ipc::send_request(&socket, &request)? {
+                println!("{}", value);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+    let (start_path, selected) = match &args.path {
+        Some(path) => file_ops::resolve_start_path(path)?,
+        None => (std::env::current_dir()?, None),
+    };
+    // Paths piped on stdin replace the directory listing
+    let listing = if stdin_has_paths() {
+        Listing::Paths(read_stdin_paths()?)
+    } else {
+        Listing::Directory
+    };
     let config = config::get_config();
-    let mut explorer = FileExplorer::new(config)?;
+    let mut explorer = FileExplorer::new(config, &start_path, selected.as_deref(), listing)?;
     if let Some(path) = explorer.run()? {
+        if let Some(cwd_file) = &args.cwd_file {
+            std::fs::write(cwd_file, path.as_os_str().as_encoded_bytes())?;
+        }
         if args.out {
             println!("{}", path.display());
             stdout().flush()?;
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Whether stdin is a pipe or a file. Other inputs, like `/dev/null` when
+/// launched from an editor, don't hold paths.
+fn stdin_has_paths() -> bool {
+    let Ok(fd) = io::stdin().as_fd().try_clone_to_owned() else {
+        return false;
+    };
+    std::fs::File::from(fd)
+        .metadata()
+        .is_ok_and(|meta| meta.file_type().is_fifo() || meta.is_file())
+}
+
+fn read_stdin_paths() -> Result<Vec<PathBuf>> {
+    let cwd = std::env::current_dir()?;
+    let mut paths = Vec::new();
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if !line.is_empty() {
+            paths.push(cwd.join(line));
+        }
+    }
+    Ok(paths)
+}
@@ -0,0 +1,232 @@
+use regex::{Regex, RegexBuilder};
+
+/// How the search prompt matches names. All modes are case insensitive
+/// unless the query contains an uppercase letter.
+#[derive(Clone, Copy, PartialEq)]
+pub enum MatchMode {
+    /// The query's characters appear in order, not necessarily together
+    Fuzzy,
+    /// The query appears as is
+    Text,
+    Regex,
+}
+
+impl MatchMode {
+    pub fn next(self) -> Self {
+        match self {
+            MatchMode::Fuzzy => MatchMode::Text,
+            MatchMode::Text => MatchMode::Regex,
+            MatchMode::Regex => MatchMode::Fuzzy,
+        }
+    }
+}
+
+/// A name matching the query.
+pub struct Match {
+    /// Higher is better
+    pub score: i64,
+    /// Indices of the matched characters in the name
+    pub positions: Vec<usize>,
+}
+
+pub struct Matcher {
+    mode: MatchMode,
+    text: String,
+    query: Vec<char>,
+    ignore_case: bool,
+    /// The compiled query in regex mode, `None` while it isn't valid
+    regex: Option<Regex>,
+}
+
+impl Matcher {
+    pub fn new(mode: MatchMode, query: &str) -> Self {
+        let ignore_case = !query.chars().any(char::is_uppercase);
+        let regex = match mode {
+            MatchMode::Regex => RegexBuilder::new(query).case_insensitive(ignore_case).build().ok(),
+            _ => None,
+        };
+        Self {
+            mode,
+            text: query.to_string(),
+            query: fold_case(query, ignore_case),
+            ignore_case,
+            regex,
+        }
+    }
+
+    pub fn mode(&self) -> MatchMode {
+        self.mode
+    }
+
+    pub fn query(&self) -> &str {
+        &self.text
+    }
+
+    pub fn matches(&self, name: &str) -> Option<Match> {
+        match self.mode {
+            MatchMode::Fuzzy => self.fuzzy_match(name),
+            MatchMode::Text => self.text_match(name),
+            MatchMode::Regex => self.regex_match(name),
+        }
+    }
+
+    /// Finds where the query's characters first all appear in order in
+    /// `name`, then scores the characters matched up to there. Consecutive
+    /// matches and matches at the start of a word score higher, gaps lower.
+    fn fuzzy_match(&self, name: &str) -> Option<Match> {
+        let text = fold_case(name, self.ignore_case);
+        let mut remaining = self.query.iter().peekable();
+        let mut end = None;
+        for (i, c) in text.iter().enumerate() {
+            if remaining.next_if_eq(&c).is_some() && remaining.peek().is_none() {
+                end = Some(i);
+                break;
+            }
+        }
+        let Some(end) = end else {
+            // Only an empty query matches without an end
+            return self.query.is_empty().then(|| Match { score: 0, positions: Vec::new() });
+        };
+
+        // Going backwards from the end picks the latest occurrences, which
+        // keeps the matched characters together
+        let mut remaining = self.query.iter().rev().peekable();
+        let mut positions = Vec::new();
+        for i in (0..=end).rev() {
+            if remaining.next_if_eq(&&text[i]).is_some() {
+                positions.push(i);
+                if remaining.peek().is_none() {
+                    break;
+                }
+            }
+        }
+        positions.reverse();
+
+        let name: Vec<char> = name.chars().collect();
+        let mut score = 0;
+        for (n, &i) in positions.iter().enumerate() {
+            score += 16;
+            match n.checked_sub(1).map(|previous| positions[previous]) {
+                Some(previous) if previous + 1 == i => score += 12,
+                Some(previous) => score -= 3 + (i - previous - 2) as i64,
+                None => {}
+            }
+            if is_word_start(&name, i) {
+                score += 10;
+            }
+        }
+        Some(Match { score: score - tie_breaker(&name), positions })
+    }
+
+    fn text_match(&self, name: &str) -> Option<Match> {
+        if self.query.is_empty() {
+            return Some(Match { score: 0, positions: Vec::new() });
+        }
+        let text = fold_case(name, self.ignore_case);
+        let start = text.windows(self.query.len()).position(|window| window == self.query.as_slice())?;
+        let name: Vec<char> = name.chars().collect();
+        let mut score = 0;
+        if is_word_start(&name, start) {
+            score += 10;
+        }
+        Some(Match {
+            score: score - start as i64 - tie_breaker(&name),
+            positions: (start..start + self.query.len()).collect(),
+        })
+    }
+
+    fn regex_match(&self, name: &str) -> Option<Match> {
+        let found = self.regex.as_ref()?.find(name)?;
+        let start = name[..found.start()].chars().count();
+        let length = found.as_str().chars().count();
+        let name: Vec<char> = name.chars().collect();
+        Some(Match {
+            score: -(start as i64) - tie_breaker(&name),
+            positions: (start..start + length).collect(),
+        })
+    }
+}
+
+/// Lowercases each character on its own, so that indices keep pointing to
+/// the same characters.
+fn fold_case(text: &str, ignore_case: bool) -> Vec<char> {
+    text.chars()
+        .map(|c| if ignore_case { c.to_lowercase().next().unwrap_or(c) } else { c })
+        .collect()
+}
+
+fn is_word_start(name: &[char], index: usize) -> bool {
+    let Some(&previous) = index.checked_sub(1).and_then(|i| name.get(i)) else {
+        return true;
+    };
+    let current = name[index];
+    matches!(previous, '_' | '-' | '.' | '/' | ' ')
+        || (previous.is_lowercase() && current.is_uppercase())
+        || (!previous.is_ascii_digit() && current.is_ascii_digit())
+}
+
+/// Between equal matches, shorter names rank first.
+fn tie_breaker(name: &[char]) -> i64 {
+    name.len() as i64 / 8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fuzzy(query: &str, name: &str) -> Option<Match> {
+        Matcher::new(MatchMode::Fuzzy, query).matches(name)
+    }
+
+    #[test]
+    fn lowercase_query_ignores_case() {
+        assert!(fuzzy("readme", "README.md").is_some());
+        assert!(Matcher::new(MatchMode::Text, "main").matches("Main.rs").is_some());
+        assert!(Matcher::new(MatchMode::Regex, "^m.*s$").matches("MAIN.RS").is_some());
+    }
+
+    #[test]
+    fn uppercase_query_matches_case() {
+        assert!(fuzzy("Readme", "readme.md").is_none());
+        assert!(fuzzy("Readme", "Readme.md").is_some());
+        assert!(Matcher::new(MatchMode::Text, "Main").matches("main.rs").is_none());
+        assert!(Matcher::new(MatchMode::Regex, "M").matches("main.rs").is_none());
+    }
+
+    #[test]
+    fn fuzzy_match_ends_at_first_complete_occurrence() {
+        // The query is complete at the first `b`, the later `ab` isn't reached
+        assert_eq!(fuzzy("ab", "a_b_ab").unwrap().positions, vec![0, 2]);
+        assert_eq!(fuzzy("abc", "xaxbxcabc").unwrap().positions, vec![1, 3, 5]);
+    }
+
+    #[test]
+    fn fuzzy_match_prefers_latest_occurrences_before_end() {
+        // Both `a`s come before the end, the one next to `b` is picked
+        assert_eq!(fuzzy("ab", "aab").unwrap().positions, vec![1, 2]);
+    }
+
+    #[test]
+    fn fuzzy_match_needs_every_character_in_order() {
+        assert!(fuzzy("ba", "ab").is_none());
+        assert!(fuzzy("abc", "ab").is_none());
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        let found = fuzzy("", "anything").unwrap();
+        assert_eq!(found.score, 0);
+        assert!(found.positions.is_empty());
+    }
+
+    #[test]
+    fn consecutive_and_word_start_matches_score_higher() {
+        assert!(fuzzy("ab", "ab.txt").unwrap().score > fuzzy("ab", "axb.txt").unwrap().score);
+        assert!(fuzzy("b", "a_b").unwrap().score > fuzzy("b", "aab").unwrap().score);
+    }
+
+    #[test]
+    fn invalid_regex_matches_nothing() {
+        assert!(Matcher::new(MatchMode::Regex, "(").matches("(").is_none());
+    }
+}
@@ -1,3 +1,4 @@
+use std::path::PathBuf;
 use crate::history::Operation;
 
 #[derive(PartialEq)]
@@ -6,11 +7,57 @@ pub enum Mode {
     Search,
     Create,
     Rename,
+    Copy,
+    Move,
+    Find,
+    Grep,
+    Filter,
+}
+
+impl Mode {
+    /// Name of the mode, as exposed to Lua.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::Normal => "normal",
+            Mode::Search => "search",
+            Mode::Create => "create",
+            Mode::Rename => "rename",
+            Mode::Copy => "copy",
+            Mode::Move => "move",
+            Mode::Find => "find",
+            Mode::Grep => "grep",
+            Mode::Filter => "filter",
+        }
+    }
 }
 
 pub enum ModeAction {
     Select(usize),
     CreateEntry(Operation),
     RenameEntry(Operation),
+    CopyTo(PathBuf),
+    MoveTo(PathBuf),
+    /// Search the tree below the current directory for names containing this
+    Find(String),
+    /// Search the files below the current directory for lines matching this
+    Grep(String),
+    /// Only show the entries matching this, cleared when empty
+    Filter(String),
     Exit,
 }
+
+/// Actions that reach beyond the active tab, carried out by `FileExplorer`.
+pub enum ExplorerAction {
+    Quit,
+    QuitAndCd(PathBuf),
+    NewTab,
+    CloseTab,
+    NextTab,
+    PreviousTab,
+    SwitchTab(usize),
+    ToggleDualPane,
+    /// Show the parent and selected directories next to the listing
+    ToggleColumns,
+    /// Copy or move the marked entries, defaulting to the other pane
+    Transfer(Mode),
+}
@@ -1,14 +1,21 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use crate::modes::{Mode, ModeAction};
 use crate::error::Result;
 use crate::history::Operation;
 use crate::file_ops;
+use crate::git;
+use crate::matcher::{MatchMode, Matcher};
 
 pub struct Prompt {
     query: String,
     mode: Mode,
+    /// Matching entries, best first
     matches: Vec<usize>,
+    /// Matched characters in the name of each matching entry
+    positions: HashMap<usize, Vec<usize>>,
     current_match: usize,
+    match_mode: MatchMode,
 }
 
 impl Prompt {
@@ -17,7 +24,9 @@ impl Prompt {
             query: String::new(),
             mode: Mode::Normal,
             matches: Vec::new(),
+            positions: HashMap::new(),
             current_match: 0,
+            match_mode: MatchMode::Fuzzy,
         }
     }
 
@@ -25,6 +34,7 @@ impl Prompt {
         self.mode = mode;
         self.query.clear();
         self.matches.clear();
+        self.positions.clear();
         self.current_match = 0;
     }
 
@@ -32,6 +42,7 @@ impl Prompt {
         self.mode = mode;
         self.query = text.to_string();
         self.matches.clear();
+        self.positions.clear();
         self.current_match = 0;
     }
 
@@ -49,15 +60,46 @@ impl Prompt {
 
     pub fn get_prompt_prefix(&self) -> &str {
         match self.mode {
-            Mode::Search => "Search: ",
+            Mode::Search => match self.match_mode {
+                MatchMode::Fuzzy => "Search: ",
+                MatchMode::Text => "Search (text): ",
+                MatchMode::Regex => "Search (regex): ",
+            },
+            Mode::Filter => match self.match_mode {
+                MatchMode::Fuzzy => "Filter: ",
+                MatchMode::Text => "Filter (text): ",
+                MatchMode::Regex => "Filter (regex): ",
+            },
             Mode::Create => "Create: ",
             Mode::Rename => "Rename: ",
+            Mode::Copy => "Copy to: ",
+            Mode::Move => "Move to: ",
+            Mode::Find => "Find: ",
+            Mode::Grep => "Grep: ",
             Mode::Normal => "",
         }
     }
 
     pub fn is_match(&self, index: usize) -> bool {
-        self.mode == Mode::Search && self.matches.contains(&index)
+        self.mode == Mode::Search && self.positions.contains_key(&index)
+    }
+
+    /// Characters of the entry's name matched by the search being typed.
+    pub fn match_positions(&self, index: usize) -> Option<&[usize]> {
+        if self.mode != Mode::Search {
+            return None;
+        }
+        self.positions.get(&index).map(Vec::as_slice)
+    }
+
+    pub fn match_mode(&self) -> MatchMode {
+        self.match_mode
+    }
+
+    /// Switches the search between fuzzy, plain text and regex matching.
+    pub fn cycle_match_mode(&mut self, entries: &[PathBuf]) {
+        self.match_mode = self.match_mode.next();
+        self.update_matches(entries);
     }
 
     fn handle_search(&mut self, input: char, entries: &[PathBuf]) -> Option<ModeAction> {
@@ -116,7 +158,7 @@ impl Prompt {
         }
     }
 
-    fn handle_rename(&mut self, input: char, selected_path: &Path) -> Result<Option<ModeAction>> {
+    fn handle_rename(&mut self, input: char, selected_path: &Path, git_mv: bool) -> Result<Option<ModeAction>> {
         match input {
             '\n' => {
                 if self.query.is_empty() {
@@ -125,17 +167,21 @@ impl Prompt {
                 }
 
                 let new_path = selected_path.parent().unwrap().join(&self.query);
-                file_ops::rename_path(selected_path, &new_path)?;
-                if selected_path != new_path {
-                    let operation = Operation::Rename {
-                        old_path: selected_path.to_path_buf(),
-                        new_path,
-                    };
+                if selected_path == new_path {
                     self.mode = Mode::Normal;
-                    return Ok(Some(ModeAction::RenameEntry(operation)));
+                    return Ok(Some(ModeAction::Exit));
+                }
+                if git_mv {
+                    git::rename(selected_path, &new_path)?;
+                } else {
+                    file_ops::rename_path(selected_path, &new_path)?;
                 }
+                let operation = Operation::Rename {
+                    old_path: selected_path.to_path_buf(),
+                    new_path,
+                };
                 self.mode = Mode::Normal;
-                Ok(Some(ModeAction::Exit))
+                Ok(Some(ModeAction::RenameEntry(operation)))
             },
             '\x7f' => {
                 self.query.pop();
@@ -148,7 +194,77 @@ impl Prompt {
         }
     }
 
-    pub fn handle_input(&mut self, input: char, entries: &[PathBuf], current_path: &Path, selected_path: Option<&PathBuf>) -> Result<Option<ModeAction>> {
+    fn handle_transfer(&mut self, input: char, current_path: &Path) -> Option<ModeAction> {
+        match input {
+            '\n' => {
+                let is_move = self.mode == Mode::Move;
+                self.mode = Mode::Normal;
+                if self.query.is_empty() {
+                    return Some(ModeAction::Exit);
+                }
+                let target = current_path.join(&self.query);
+                if is_move {
+                    Some(ModeAction::MoveTo(target))
+                } else {
+                    Some(ModeAction::CopyTo(target))
+                }
+            },
+            '\x7f' => {
+                self.query.pop();
+                None
+            },
+            c => {
+                self.query.push(c);
+                None
+            }
+        }
+    }
+
+    fn handle_find(&mut self, input: char) -> Option<ModeAction> {
+        match input {
+            '\n' => {
+                let is_grep = self.mode == Mode::Grep;
+                self.mode = Mode::Normal;
+                if self.query.is_empty() {
+                    return Some(ModeAction::Exit);
+                }
+                if is_grep {
+                    Some(ModeAction::Grep(self.query.clone()))
+                } else {
+                    Some(ModeAction::Find(self.query.clone()))
+                }
+            },
+            '\x7f' => {
+                self.query.pop();
+                None
+            },
+            c => {
+                self.query.push(c);
+                None
+            }
+        }
+    }
+
+    /// Filters the listing as the query is typed.
+    fn handle_filter(&mut self, input: char) -> Option<ModeAction> {
+        match input {
+            '\n' => self.mode = Mode::Normal,
+            '\x7f' => {
+                self.query.pop();
+            },
+            c => self.query.push(c),
+        }
+        Some(ModeAction::Filter(self.query.clone()))
+    }
+
+    pub fn handle_input(
+        &mut self,
+        input: char,
+        entries: &[PathBuf],
+        current_path: &Path,
+        selected_path: Option<&PathBuf>,
+        git_mv: bool,
+    ) -> Result<Option<ModeAction>> {
         match self.mode {
             Mode::Search => {
                 if input == '\n' {
@@ -159,30 +275,34 @@ impl Prompt {
             Mode::Create => self.handle_create(input, current_path),
             Mode::Rename => {
                 if let Some(path) = selected_path {
-                    self.handle_rename(input, path)
+                    self.handle_rename(input, path, git_mv)
                 } else {
                     Ok(Some(ModeAction::Exit))
                 }
             },
+            Mode::Copy | Mode::Move => Ok(self.handle_transfer(input, current_path)),
+            Mode::Find | Mode::Grep => Ok(self.handle_find(input)),
+            Mode::Filter => Ok(self.handle_filter(input)),
             Mode::Normal => Ok(None),
         }
     }
 
+    /// Ranks the entries matching the query by score, keeping the listing
+    /// order between equal scores.
     pub fn update_matches(&mut self, entries: &[PathBuf]) {
-        self.matches = entries.iter().skip(1).enumerate()
-            .filter_map(|(i, entry)| {
-                let name = entry.file_name()
-                    .unwrap_or_default()
-                    .to_string_lossy()
-                    .to_lowercase();
-                
-                if name.contains(&self.query.to_lowercase()) {
-                    Some(i + 1)
-                } else {
-                    None
-                }
-            })
-            .collect();
+        let matcher = Matcher::new(self.match_mode, &self.query);
+        let mut scored: Vec<(usize, i64)> = Vec::new();
+        self.positions.clear();
+        for (i, entry) in entries.iter().enumerate().skip(1) {
+            let name = entry.file_name().unwrap_or_default().to_string_lossy();
+            if let Some(found) = matcher.matches(&name) {
+                scored.push((i, found.score));
+                self.positions.insert(i, found.positions);
+            }
+        }
+        scored.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+        self.matches = scored.into_iter().map(|(i, _)| i).collect();
+        self.current_match = 0;
     }
 
     pub fn next_match(&mut self) -> Option<usize> {
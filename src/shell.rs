@@ -0,0 +1,39 @@
+/// Shells for which `rx init` can print a wrapper function.
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+const POSIX_WRAPPER: &str = r#"rx() {
+    local tmp cwd
+    tmp="$(mktemp -t rx-cwd.XXXXXX)"
+    command rx --cwd-file "$tmp" "$@"
+    cwd="$(cat -- "$tmp")"
+    if [ -n "$cwd" ] && [ "$cwd" != "$PWD" ]; then
+        builtin cd -- "$cwd"
+    fi
+    rm -f -- "$tmp"
+}
+"#;
+
+const FISH_WRAPPER: &str = r#"function rx
+    set -l tmp (mktemp -t rx-cwd.XXXXXX)
+    command rx --cwd-file $tmp $argv
+    set -l cwd (cat -- $tmp)
+    if test -n "$cwd"; and test "$cwd" != "$PWD"
+        builtin cd -- $cwd
+    end
+    rm -f -- $tmp
+end
+"#;
+
+/// Returns a function to source in the given shell. It wraps `rx` so that
+/// leaving with "quit and cd" changes the shell's working directory.
+pub fn init_script(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash | Shell::Zsh => POSIX_WRAPPER,
+        Shell::Fish => FISH_WRAPPER,
+    }
+}
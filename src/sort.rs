@@ -0,0 +1,241 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// What the entries of a listing are sorted by.
+#[derive(Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    /// By name, with numbers compared by value so `file2` comes before `file10`
+    Natural,
+    /// Largest first
+    Size,
+    /// Most recently modified first
+    Modified,
+    /// Most recently created first
+    Created,
+    /// By extension, then by name
+    Extension,
+}
+
+impl SortKey {
+    pub fn next(self) -> Self {
+        match self {
+            SortKey::Natural => SortKey::Size,
+            SortKey::Size => SortKey::Modified,
+            SortKey::Modified => SortKey::Created,
+            SortKey::Created => SortKey::Extension,
+            SortKey::Extension => SortKey::Natural,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SortKey::Natural => "natural",
+            SortKey::Size => "size",
+            SortKey::Modified => "modified",
+            SortKey::Created => "created",
+            SortKey::Extension => "extension",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct SortOrder {
+    pub key: SortKey,
+    pub reverse: bool,
+    /// List directories before files, whatever the key and direction
+    pub dirs_first: bool,
+}
+
+impl SortOrder {
+    /// Short description shown in the status line, e.g. `size, reversed`.
+    pub fn label(&self) -> String {
+        let mut label = self.key.name().to_string();
+        if self.reverse {
+            label.push_str(", reversed");
+        }
+        if !self.dirs_first {
+            label.push_str(", mixed");
+        }
+        label
+    }
+
+    /// Sorts `paths` in place. The metadata is read once per path.
+    pub fn sort(&self, paths: &mut Vec<PathBuf>) {
+        let mut items: Vec<SortItem> = paths.drain(..).map(SortItem::new).collect();
+        items.sort_by(|a, b| self.compare(a, b));
+        paths.extend(items.into_iter().map(|item| item.path));
+    }
+
+    fn compare(&self, a: &SortItem, b: &SortItem) -> Ordering {
+        if self.dirs_first && a.is_dir != b.is_dir {
+            return b.is_dir.cmp(&a.is_dir);
+        }
+        let ordering = match self.key {
+            SortKey::Natural => Ordering::Equal,
+            SortKey::Size => b.size.cmp(&a.size),
+            SortKey::Modified => b.modified.cmp(&a.modified),
+            SortKey::Created => b.created.cmp(&a.created),
+            SortKey::Extension => a.extension.cmp(&b.extension),
+        }
+        .then_with(|| natural_cmp(&a.name, &b.name));
+        if self.reverse { ordering.reverse() } else { ordering }
+    }
+}
+
+struct SortItem {
+    path: PathBuf,
+    /// The whole path, so that the files of a recursive listing are grouped
+    /// by directory
+    name: String,
+    extension: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+    created: Option<SystemTime>,
+}
+
+impl SortItem {
+    fn new(path: PathBuf) -> Self {
+        let metadata = fs::metadata(&path).ok();
+        let is_dir = metadata.as_ref().is_some_and(|meta| meta.is_dir());
+        Self {
+            name: path.to_string_lossy().to_lowercase(),
+            extension: path
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .unwrap_or_default(),
+            is_dir,
+            size: metadata.as_ref().filter(|_| !is_dir).map_or(0, |meta| meta.len()),
+            modified: metadata.as_ref().and_then(|meta| meta.modified().ok()),
+            created: metadata.as_ref().and_then(|meta| meta.created().ok()),
+            path,
+        }
+    }
+}
+
+/// Sorts `items` with a "less than" function, keeping equal items in order.
+/// Unlike `sort_by`, it never panics when `less` doesn't define an order,
+/// e.g. for a function written in Lua.
+pub fn merge_sort_by<T>(mut items: Vec<T>, less: &mut impl FnMut(&T, &T) -> bool) -> Vec<T> {
+    if items.len() <= 1 {
+        return items;
+    }
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort_by(items, less);
+    let right = merge_sort_by(right, less);
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // Taking from the left on ties keeps the sort stable
+        let item = if less(b, a) { right.next() } else { left.next() };
+        merged.extend(item);
+    }
+    merged.extend(left);
+    merged.extend(right);
+    merged
+}
+
+/// Compares two strings, with runs of digits compared by their value.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a_char), Some(b_char)) if a_char.is_ascii_digit() && b_char.is_ascii_digit() => {
+                let a_number = take_number(&mut a_chars);
+                let b_number = take_number(&mut b_chars);
+                // Without leading zeros, a longer number is a larger one
+                let ordering = a_number.len()
+                    .cmp(&b_number.len())
+                    .then_with(|| a_number.cmp(&b_number));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(a_char), Some(b_char)) => {
+                let ordering = a_char.cmp(b_char);
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut number = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        if !(number.is_empty() && c == '0') {
+            number.push(c);
+        }
+    }
+    number
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_compare_by_value() {
+        assert_eq!(natural_cmp("file2", "file10"), Ordering::Less);
+        assert_eq!(natural_cmp("file10", "file9"), Ordering::Greater);
+        assert_eq!(natural_cmp("10", "10"), Ordering::Equal);
+    }
+
+    #[test]
+    fn leading_zeros_are_ignored() {
+        assert_eq!(natural_cmp("file007", "file7"), Ordering::Equal);
+        assert_eq!(natural_cmp("file010", "file9"), Ordering::Greater);
+        assert_eq!(natural_cmp("0", "000"), Ordering::Equal);
+        assert_eq!(natural_cmp("0", "1"), Ordering::Less);
+    }
+
+    #[test]
+    fn each_run_of_digits_is_compared() {
+        assert_eq!(natural_cmp("v1.9", "v1.10"), Ordering::Less);
+        assert_eq!(natural_cmp("a1b2", "a1b10"), Ordering::Less);
+        assert_eq!(natural_cmp("a10b", "a9c"), Ordering::Greater);
+        assert_eq!(natural_cmp("a2b1", "a10b1"), Ordering::Less);
+    }
+
+    #[test]
+    fn digits_and_letters_compare_as_characters() {
+        assert_eq!(natural_cmp("1abc", "abc"), Ordering::Less);
+        assert_eq!(natural_cmp("a1", "ab"), Ordering::Less);
+    }
+
+    #[test]
+    fn merge_sort_is_stable() {
+        let items = vec![(2, 'a'), (1, 'b'), (2, 'c'), (1, 'd')];
+        let sorted = merge_sort_by(items, &mut |a: &(i32, char), b: &(i32, char)| a.0 < b.0);
+        assert_eq!(sorted, vec![(1, 'b'), (1, 'd'), (2, 'a'), (2, 'c')]);
+    }
+
+    #[test]
+    fn merge_sort_survives_inconsistent_order() {
+        let items: Vec<u32> = (0..100).rev().collect();
+        let mut calls = 0u32;
+        let sorted = merge_sort_by(items, &mut |_: &u32, _: &u32| {
+            calls += 1;
+            calls.is_multiple_of(3)
+        });
+        assert_eq!(sorted.len(), 100);
+    }
+
+    #[test]
+    fn prefix_comes_first() {
+        assert_eq!(natural_cmp("file", "file1"), Ordering::Less);
+        assert_eq!(natural_cmp("", "a"), Ordering::Less);
+    }
+}
@@ -1,85 +1,831 @@
-use std::path::PathBuf;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use crate::config::Config;
 use crate::error::Result;
-use crate::file_ops;
+use crate::file_ops::{self, Visibility};
+use crate::find::{Finder, Hit, LineMatch};
+use crate::git::{Branch, GitStatus, GitStatuses};
 use crate::prompt::Prompt;
-use crate::history::{Operation};
-use crate::lua::{Entry, DisplayModuleFn};
+use crate::history::History;
+use crate::lua::{Column, ColumnSort, Entry, LineContext, ListingHooks};
+use crate::matcher::Matcher;
+use crate::sort::{self, SortOrder};
+use crate::styled::StyledText;
+use crate::terminal;
+use crate::ui::Viewport;
 
+/// Where the entries of the listing come from.
+pub enum Listing {
+    /// The contents of `current_path`.
+    Directory,
+    /// An arbitrary list of paths, e.g. read from stdin. Paths that no longer
+    /// exist are hidden, so that undoing a delete brings them back.
+    Paths(Vec<PathBuf>),
+    /// The results of a search below `current_path`, hidden like `Paths` when
+    /// they no longer exist.
+    Results(Vec<Hit>),
+    /// The contents of `current_path`, with the listed directories expanded
+    /// in place.
+    Tree(Vec<PathBuf>),
+    /// Every file below `current_path`, following the recursive listing
+    /// settings of the configuration.
+    Recursive,
+}
+
+impl Listing {
+    /// Name of the kind of listing, as exposed to Lua.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Listing::Directory => "directory",
+            Listing::Paths(_) => "paths",
+            Listing::Results(_) => "results",
+            Listing::Tree(_) => "tree",
+            Listing::Recursive => "recursive",
+        }
+    }
+}
+
+/// What the status line and header were last evaluated for: the directory,
+/// the selected and marked entries, and the mode.
+type LinesKey = (PathBuf, PathBuf, Vec<PathBuf>, &'static str);
+
+/// The state of a single tab. The configuration, display modules and undo
+/// history are shared with the other tabs.
 pub struct AppState {
     pub current_path: PathBuf,
+    pub listing: Listing,
     pub entries: Vec<PathBuf>,
+    /// Tree level of each entry, 0 outside of tree listings
+    pub depths: Vec<usize>,
+    /// Indentation guides drawn in front of each entry of a tree listing
+    pub guides: Vec<String>,
+    /// Matching line of each entry of content search results
+    pub lines: Vec<Option<LineMatch>>,
     pub selected: usize,
+    pub marked: Vec<PathBuf>,
+    /// Sticky filter hiding the entries whose name doesn't match
+    pub filter: Option<Matcher>,
+    /// Order of directory, tree and recursive listings
+    pub sort: SortOrder,
+    /// Display module whose column the listing was sorted by, from a click
+    /// on its title
+    pub sort_column: Option<usize>,
+    /// Whether directory, tree and recursive listings show hidden and
+    /// ignored entries
+    pub visibility: Visibility,
+    /// Git status of the paths below `current_path`, read again when files
+    /// may have changed, and shared with listings of subdirectories
+    git: Option<Rc<GitStatuses>>,
+    pub viewport: Viewport,
     pub prompt: Prompt,
-    pub config: Config,
+    pub config: Rc<Config>,
     pub delete_mode: Option<usize>,
-    pub history: Vec<Operation>,
-    pub history_index: usize,
-    pub display_modules: Vec<DisplayModuleFn>,
-    pub modules_cache: Vec<Vec<String>>,
+    /// Whether `X` was pressed once, asking to discard changes
+    pub discard_mode: bool,
+    /// Shown on the status line until the next key press
+    pub message: Option<String>,
+    pub history: Rc<RefCell<History>>,
+    history_generation: usize,
+    pub display_modules: Rc<Vec<Column>>,
+    pub hooks: Rc<ListingHooks>,
+    pub modules_cache: Vec<Vec<StyledText>>,
     pub max_widths: Vec<usize>,
+    /// Parts of the status line and header set from Lua
+    pub status_line: Vec<String>,
+    pub header: Vec<String>,
+    /// Text of the status bar segments added from Lua, by name
+    pub status_segments: HashMap<String, String>,
+    lines_key: Option<LinesKey>,
+    /// Search adding its results to the listing
+    finder: Option<Finder>,
 }
 
 impl AppState {
-    pub fn new(config: Config, display_modules: Vec<DisplayModuleFn>) -> Result<Self> {
-        let current_path = std::env::current_dir()?;
-        let entries = file_ops::read_dir_entries(&current_path)?;
-        
-        let mut state = Self {
-            current_path,
-            entries,
+    pub fn new(
+        config: Config,
+        display_modules: Vec<Column>,
+        hooks: ListingHooks,
+        path: &Path,
+        listing: Listing,
+    ) -> Result<Self> {
+        let mut state = Self::with_shared(
+            Rc::new(config),
+            Rc::new(RefCell::new(History::default())),
+            Rc::new(display_modules),
+            Rc::new(hooks),
+            path,
+            listing,
+        );
+        state.refresh()?;
+        Ok(state)
+    }
+
+    /// Builds a state with the sort order and visibility of the
+    /// configuration. Its entries are read by the first `refresh`.
+    fn with_shared(
+        config: Rc<Config>,
+        history: Rc<RefCell<History>>,
+        display_modules: Rc<Vec<Column>>,
+        hooks: Rc<ListingHooks>,
+        path: &Path,
+        listing: Listing,
+    ) -> Self {
+        Self {
+            current_path: path.to_path_buf(),
+            listing,
+            entries: Vec::new(),
+            depths: Vec::new(),
+            guides: Vec::new(),
+            lines: Vec::new(),
             selected: 1,
+            marked: Vec::new(),
+            filter: None,
+            sort: SortOrder {
+                key: config.sort,
+                reverse: config.sort_reverse,
+                dirs_first: config.dirs_first,
+            },
+            sort_column: None,
+            visibility: Visibility {
+                show_hidden: config.show_hidden,
+                hide_ignored: config.hide_ignored,
+            },
+            git: None,
+            viewport: Viewport::new(),
             prompt: Prompt::new(),
             config,
             delete_mode: None,
-            history: vec![],
-            history_index: 0,
+            discard_mode: false,
+            message: None,
+            history,
+            history_generation: 0,
             display_modules,
+            hooks,
             modules_cache: Vec::new(),
             max_widths: Vec::new(),
-        };
-        state.recompute_display_data();
+            status_line: Vec::new(),
+            header: Vec::new(),
+            status_segments: HashMap::new(),
+            lines_key: None,
+            finder: None,
+        }
+    }
+
+    /// Opens a new tab on the directory listing of `path`, sharing this
+    /// tab's configuration, display modules, Lua hooks and history.
+    pub fn new_tab(&self, path: &Path) -> Result<Self> {
+        self.with_modules(path, self.display_modules.clone())
+    }
+
+    /// Opens a directory listing of `path` that is shown with other display
+    /// modules, e.g. a side column of the columns view. It starts with this
+    /// listing's sort order and visibility.
+    pub fn with_modules(&self, path: &Path, display_modules: Rc<Vec<Column>>) -> Result<Self> {
+        let mut state = Self::with_shared(
+            self.config.clone(),
+            self.history.clone(),
+            display_modules,
+            self.hooks.clone(),
+            path,
+            Listing::Directory,
+        );
+        state.sort = self.sort;
+        // Other modules don't have the same columns
+        if Rc::ptr_eq(&state.display_modules, &self.display_modules) {
+            state.sort_column = self.sort_column;
+        }
+        state.visibility = self.visibility;
+        // The status of this directory covers its subdirectories
+        match &self.git {
+            Some(git) if path.starts_with(&self.current_path) => {
+                state.git = Some(git.clone());
+                state.relist()?;
+            }
+            _ => state.refresh()?,
+        }
         Ok(state)
     }
 
-    pub fn recompute_display_data(&mut self) {
-        self.modules_cache.clear();
-        for (idx, entry) in self.entries.iter().enumerate() {
-            let info = self.create_entry(entry, self.get_display_name(entry, idx));
-            let parts = self.display_modules
+    /// Reloads the git status and the entries from the listing's source, and
+    /// recomputes the display data.
+    pub fn refresh(&mut self) -> Result<()> {
+        self.git = self.config.git_status
+            .then(|| GitStatuses::load(&self.current_path))
+            .flatten()
+            .map(Rc::new);
+        self.relist()
+    }
+
+    /// Reloads the entries, keeping the git status. Changing how the same
+    /// files are listed doesn't change their status.
+    fn relist(&mut self) -> Result<()> {
+        self.lines.clear();
+        self.entries = match &self.listing {
+            Listing::Directory => file_ops::read_dir_entries(&self.current_path, &self.sort, self.visibility)?,
+            Listing::Paths(paths) => {
+                let mut entries = vec![self.current_path.join("..")];
+                entries.extend(paths.iter().filter(|p| p.exists() || p.is_symlink()).cloned());
+                entries
+            }
+            Listing::Results(hits) => {
+                let mut entries = vec![self.current_path.join("..")];
+                self.lines.push(None);
+                for hit in hits.iter().filter(|hit| hit.path.exists() || hit.path.is_symlink()) {
+                    entries.push(hit.path.clone());
+                    self.lines.push(hit.line.clone());
+                }
+                entries
+            }
+            Listing::Tree(expanded) => {
+                file_ops::read_tree_entries(&self.current_path, expanded, &self.sort, self.visibility)?
+            }
+            Listing::Recursive => file_ops::read_recursive_entries(
+                &self.current_path,
+                self.config.recursive_max_depth,
+                &self.config.ignore,
+                &self.sort,
+                self.visibility,
+            )?,
+        };
+        self.lines.resize(self.entries.len(), None);
+        if self.filter.is_some() || self.hooks.filter.is_some() {
+            let mut error = None;
+            let keep: Vec<bool> = self.entries
                 .iter()
-                .map(|m| m(&info))
+                .enumerate()
+                .map(|(i, entry)| i == 0 || self.passes_filter(entry, i, &mut error))
                 .collect();
-            self.modules_cache.push(parts);
+            self.report_filter_error(error);
+            let mut kept = keep.iter();
+            self.entries.retain(|_| *kept.next().unwrap());
+            let mut kept = keep.iter();
+            self.lines.retain(|_| *kept.next().unwrap());
+        }
+        // Reordering a tree or search results would break them up
+        if matches!(self.listing, Listing::Directory | Listing::Recursive) {
+            let modules = self.display_modules.clone();
+            match self.sort_column.map(|index| &modules[index]) {
+                // The entries were read in the built-in order of the column
+                Some(Column { sort: ColumnSort::Key(_), .. }) => {}
+                Some(column) => self.sort_by_values(column),
+                None => self.sort_with_hook(),
+            }
+        }
+        self.depths = self.entries
+            .iter()
+            .map(|entry| match entry.strip_prefix(&self.current_path) {
+                Ok(relative) if matches!(self.listing, Listing::Tree(_)) => relative.components().count() - 1,
+                _ => 0,
+            })
+            .collect();
+        self.selected = self.selected.min(self.entries.len() - 1);
+        self.marked.retain(|p| p.exists() || p.is_symlink());
+        self.history_generation = self.history.borrow().generation();
+        self.recompute_display_data();
+        self.lines_key = None;
+        Ok(())
+    }
+
+    /// Sorts the rows by the values of `column`, keeping the directories
+    /// first when the sort order does.
+    fn sort_by_values(&mut self, column: &Column) {
+        let mut rows: Vec<(Entry, _)> = (1..self.entries.len())
+            .map(|i| self.entry_at(i))
+            .map(|entry| {
+                let value = column.sort_value(&entry);
+                (entry, value)
+            })
+            .collect();
+        rows.sort_by(|(a, a_value), (b, b_value)| {
+            if self.sort.dirs_first && a.is_dir != b.is_dir {
+                return b.is_dir.cmp(&a.is_dir);
+            }
+            let ordering = match (a_value, b_value) {
+                (Some(a_value), Some(b_value)) => a_value.compare(b_value),
+                _ => std::cmp::Ordering::Equal,
+            };
+            if self.sort.reverse { ordering.reverse() } else { ordering }
+        });
+        self.entries.truncate(1);
+        self.entries.extend(rows.into_iter().map(|(entry, _)| entry.path));
+    }
+
+    /// Sorts the rows with the sorter set from Lua, if any. When it fails,
+    /// or puts an entry before one it should come after, the rows keep the
+    /// built-in order and the problem is shown on the status line.
+    fn sort_with_hook(&mut self) {
+        let Some(sorter) = &self.hooks.sorter else {
+            return;
+        };
+        let rows: Vec<Entry> = (1..self.entries.len()).map(|i| self.entry_at(i)).collect();
+        // Lua isn't called again after the first error
+        let mut error = None;
+        let mut less = |a: &Entry, b: &Entry| {
+            error.is_none() && sorter(a, b).unwrap_or_else(|err| {
+                error = Some(err);
+                false
+            })
+        };
+        let rows = sort::merge_sort_by(rows, &mut less);
+        let ordered = rows.windows(2).all(|pair| !less(&pair[1], &pair[0]));
+        match error {
+            Some(err) => self.message = Some(format!("The Lua sorter failed: {}", err)),
+            None if !ordered => {
+                self.message = Some("The Lua sorter doesn't give a consistent order, e.g. it uses <= instead of <".to_string());
+            }
+            None => {
+                self.entries.truncate(1);
+                self.entries.extend(rows.into_iter().map(|entry| entry.path));
+            }
         }
-        self.max_widths = vec![0; self.display_modules.len()];
+    }
+
+    /// Switches to the directory listing of `path`.
+    pub fn change_dir(&mut self, path: &Path) -> Result<()> {
+        std::env::set_current_dir(path)?;
+        self.current_path = std::env::current_dir()?;
+        self.finder = None;
+        self.listing = match self.listing {
+            Listing::Tree(_) => Listing::Tree(Vec::new()),
+            Listing::Recursive => Listing::Recursive,
+            _ => Listing::Directory,
+        };
+        self.selected = 1;
+        self.marked.clear();
+        self.viewport.reset();
+        self.refresh()
+    }
+
+    pub fn is_tree(&self) -> bool {
+        matches!(self.listing, Listing::Tree(_))
+    }
+
+    /// Switches between the tree and the plain listing of `current_path`.
+    pub fn toggle_tree(&mut self) -> Result<()> {
+        let listing = match self.listing {
+            Listing::Tree(_) => Listing::Directory,
+            _ => Listing::Tree(Vec::new()),
+        };
+        self.switch_listing(listing)
+    }
+
+    /// Switches between the recursive and the plain listing of `current_path`.
+    pub fn toggle_recursive(&mut self) -> Result<()> {
+        let listing = match self.listing {
+            Listing::Recursive => Listing::Directory,
+            _ => Listing::Recursive,
+        };
+        self.switch_listing(listing)
+    }
+
+    /// Replaces the listing of `current_path`, keeping the selection on the
+    /// selected entry or, when it is no longer listed, its top-level ancestor.
+    fn switch_listing(&mut self, listing: Listing) -> Result<()> {
+        let selected = self.entries[self.selected].clone();
+        self.listing = listing;
+        self.finder = None;
+        self.marked.clear();
+        self.relist()?;
+        if self.entries.contains(&selected) {
+            self.select_path(&selected);
+            return Ok(());
+        }
+        let top_level = selected
+            .strip_prefix(&self.current_path)
+            .ok()
+            .and_then(|relative| relative.components().next())
+            .map(|name| self.current_path.join(name));
+        if let Some(path) = top_level {
+            self.select_path(&path);
+        }
+        Ok(())
+    }
+
+    /// Whether `path` is expanded in a tree listing.
+    pub fn is_expanded(&self, path: &Path) -> bool {
+        matches!(&self.listing, Listing::Tree(expanded) if expanded.iter().any(|p| p == path))
+    }
+
+    /// Expands or collapses the directory at `path` in a tree listing.
+    pub fn toggle_expanded(&mut self, path: &Path) -> Result<()> {
+        if let Listing::Tree(expanded) = &mut self.listing {
+            if let Some(index) = expanded.iter().position(|p| p == path) {
+                expanded.remove(index);
+            } else {
+                expanded.push(path.to_path_buf());
+            }
+            self.relist()?;
+        }
+        Ok(())
+    }
+
+    /// Sets or clears the filter, keeping the selected entry selected if it
+    /// still passes.
+    pub fn set_filter(&mut self, filter: Option<Matcher>) -> Result<()> {
+        self.filter = filter;
+        self.refresh_keeping_selection()
+    }
+
+    /// Re-sorts the listing, keeping the selected entry selected. Another
+    /// key stops sorting by a column.
+    pub fn set_sort(&mut self, sort: SortOrder) -> Result<()> {
+        if sort.key != self.sort.key {
+            self.sort_column = None;
+        }
+        self.sort = sort;
+        self.refresh_keeping_selection()
+    }
+
+    /// Sorts by the column of the module at `index`, or reverses the order
+    /// when the listing is already sorted by it.
+    pub fn sort_by_column(&mut self, index: usize) -> Result<()> {
+        if self.sort_column == Some(index) {
+            self.sort.reverse = !self.sort.reverse;
+        } else {
+            self.sort.reverse = false;
+            if let ColumnSort::Key(key) = self.display_modules[index].sort {
+                self.sort.key = key;
+            }
+            self.sort_column = Some(index);
+        }
+        self.refresh_keeping_selection()
+    }
+
+    /// Shows or hides the hidden and ignored entries, keeping the selected
+    /// entry selected if it is still listed.
+    pub fn set_visibility(&mut self, visibility: Visibility) -> Result<()> {
+        self.visibility = visibility;
+        self.refresh_keeping_selection()
+    }
+
+    fn refresh_keeping_selection(&mut self) -> Result<()> {
+        let selected = self.entries[self.selected].clone();
+        self.relist()?;
+        self.select_path(&selected);
+        self.viewport.update(self.selected, self.entries.len());
+        Ok(())
+    }
+
+    /// Whether the entry at `index` passes the sticky filter and the Lua one.
+    /// Entries are kept once the Lua filter failed, and it isn't called again
+    /// after its first error, which is stored in `error`.
+    fn passes_filter(&self, path: &PathBuf, index: usize, error: &mut Option<mlua::Error>) -> bool {
+        if let Some(filter) = &self.filter {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if filter.matches(&name).is_none() {
+                return false;
+            }
+        }
+        match &self.hooks.filter {
+            Some(filter) if error.is_none() => {
+                filter(&self.create_entry(path, self.get_display_name(path, index))).unwrap_or_else(|err| {
+                    *error = Some(err);
+                    true
+                })
+            }
+            _ => true,
+        }
+    }
+
+    fn report_filter_error(&mut self, error: Option<mlua::Error>) {
+        if let Some(err) = error {
+            self.message = Some(format!("The Lua filter failed: {}", err));
+        }
+    }
+
+    /// Replaces the listing with the paths below `current_path` whose name
+    /// contains `query`. The search runs in the background, its results are
+    /// added by `poll_finder` as they come.
+    pub fn find(&mut self, query: &str) -> Result<()> {
+        let finder = Finder::names(&self.current_path, query, &self.config.ignore, self.visibility.show_hidden)?;
+        self.show_results(finder)
+    }
+
+    /// Replaces the listing with the lines matching `pattern` in the files
+    /// below `current_path`, searched in the background like `find`.
+    pub fn grep(&mut self, pattern: &str) -> Result<()> {
+        let finder = Finder::contents(&self.current_path, pattern, &self.config.ignore, self.visibility.show_hidden)?;
+        self.message = finder.notice.clone();
+        self.show_results(finder)
+    }
+
+    fn show_results(&mut self, finder: Finder) -> Result<()> {
+        self.finder = Some(finder);
+        self.listing = Listing::Results(Vec::new());
+        self.marked.clear();
+        self.viewport.reset();
+        self.relist()
+    }
+
+    /// Whether a search is still adding results.
+    pub fn is_searching(&self) -> bool {
+        self.finder.is_some()
+    }
+
+    /// Adds the results found since the last call to the listing. Returns
+    /// whether there were any.
+    pub fn poll_finder(&mut self) -> bool {
+        let Some(finder) = &mut self.finder else {
+            return false;
+        };
+        let found = finder.poll();
+        if finder.is_done() {
+            self.finder = None;
+        }
+        let Listing::Results(hits) = &mut self.listing else {
+            return false;
+        };
+        if found.is_empty() {
+            return false;
+        }
+        hits.extend(found.iter().cloned());
+
+        // Only compute the display data of the new rows
+        let index = self.entries.len();
+        let mut error = None;
+        let found: Vec<Hit> = found.into_iter().filter(|hit| self.passes_filter(&hit.path, index, &mut error)).collect();
+        self.report_filter_error(error);
+        for hit in found {
+            self.entries.push(hit.path);
+            self.lines.push(hit.line);
+            self.depths.push(0);
+            self.guides.push(String::new());
+            let modules = self.modules_at(self.entries.len() - 1);
+            for ((width, column), module) in self.max_widths.iter_mut().zip(self.display_modules.iter()).zip(&modules) {
+                *width = (*width).max(column.width(module.width()));
+            }
+            self.modules_cache.push(modules);
+        }
+        // Select the first result as it comes in
+        if self.selected == 0 && self.entries.len() > 1 {
+            self.selected = 1;
+        }
+        true
+    }
+
+    /// Opens the directory containing the selected entry, with the entry
+    /// selected, e.g. to see a search result among its siblings.
+    pub fn reveal(&mut self) -> Result<()> {
+        if self.selected == 0 {
+            return Ok(());
+        }
+        let path = self.entries[self.selected].clone();
+        if let Some(parent) = path.parent() {
+            self.listing = Listing::Directory;
+            self.change_dir(parent)?;
+            self.select_path(&path);
+        }
+        Ok(())
+    }
+
+    /// Marks or unmarks the selected entry.
+    pub fn toggle_mark(&mut self) {
+        if self.selected == 0 {
+            return;
+        }
+        let path = &self.entries[self.selected];
+        if let Some(index) = self.marked.iter().position(|p| p == path) {
+            self.marked.remove(index);
+        } else {
+            self.marked.push(path.clone());
+        }
+    }
+
+    /// The entries an action applies to: the marked ones if any, otherwise
+    /// the selected one.
+    pub fn marked_or_selected(&self) -> Vec<PathBuf> {
+        if !self.marked.is_empty() {
+            self.marked.clone()
+        } else if self.selected > 0 {
+            vec![self.entries[self.selected].clone()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Whether the shared history changed files since the last refresh,
+    /// possibly from another tab.
+    pub fn is_outdated(&self) -> bool {
+        self.history.borrow().generation() != self.history_generation
+    }
+
+    /// Keeps a path listing in sync after `old` was renamed to `new`, and a
+    /// tree listing expanded below it.
+    pub fn path_renamed(&mut self, old: &Path, new: &Path) {
+        match &mut self.listing {
+            Listing::Paths(paths) => {
+                for path in paths.iter_mut().filter(|p| *p == old) {
+                    *path = new.to_path_buf();
+                }
+            }
+            Listing::Results(hits) => {
+                for hit in hits.iter_mut() {
+                    if let Ok(relative) = hit.path.strip_prefix(old) {
+                        hit.path = new.join(relative);
+                    }
+                }
+            }
+            Listing::Tree(expanded) => {
+                for path in expanded.iter_mut() {
+                    if let Ok(relative) = path.strip_prefix(old) {
+                        *path = new.join(relative);
+                    }
+                }
+            }
+            Listing::Directory | Listing::Recursive => {}
+        }
+    }
+
+    /// Adds a newly created path to a path listing or search results.
+    pub fn path_created(&mut self, path: &Path) {
+        match &mut self.listing {
+            Listing::Paths(paths) if !paths.iter().any(|p| p == path) => {
+                paths.push(path.to_path_buf());
+            }
+            Listing::Results(hits) if !hits.iter().any(|hit| hit.path == path) => {
+                hits.push(Hit { path: path.to_path_buf(), line: None });
+            }
+            _ => {}
+        }
+    }
+
+    pub fn select_path(&mut self, path: &Path) {
+        if let Some(index) = self.entries.iter().skip(1).position(|entry| entry == path) {
+            self.selected = index + 1;
+            self.viewport.update(self.selected, self.entries.len());
+        }
+    }
+
+    pub fn recompute_display_data(&mut self) {
+        self.modules_cache = (0..self.entries.len())
+            .map(|idx| self.modules_at(idx))
+            .collect();
+        self.max_widths = self.display_modules.iter().map(|column| column.width(0)).collect();
         for parts in self.modules_cache.iter().skip(1) {
             for (i, s) in parts.iter().enumerate() {
-                use unicode_width::UnicodeWidthStr;
-                self.max_widths[i] = self.max_widths[i].max(s.width());
+                self.max_widths[i] = self.max_widths[i].max(self.display_modules[i].width(s.width()));
+            }
+        }
+        self.guides = Self::tree_guides(&self.depths);
+        let guide_width = self.guides.iter().skip(1).map(|guide| guide.chars().count()).max().unwrap_or(0);
+        let column = terminal::guided_module(&self.max_widths);
+        if let Some(width) = self.max_widths.get_mut(column) {
+            *width += guide_width;
+        }
+    }
+
+    /// Re-evaluates the status line, header and status bar segments set from
+    /// Lua, when the directory, the selection or the mode changed since the
+    /// last call or the listing was reloaded.
+    pub fn update_lines(&mut self) {
+        let hooks = &self.hooks;
+        if hooks.status_line.is_empty() && hooks.header.is_empty() && hooks.status_segments.is_empty() {
+            return;
+        }
+        let key = (
+            self.current_path.clone(),
+            self.entries[self.selected].clone(),
+            self.marked.clone(),
+            self.prompt.get_mode().name(),
+        );
+        if self.lines_key.as_ref() == Some(&key) {
+            return;
+        }
+        let context = LineContext {
+            path: self.current_path.clone(),
+            entry: self.entry_at(self.selected),
+            selection: self.marked.clone(),
+            mode: key.3,
+            listing: self.listing.name(),
+        };
+        self.status_line = self.hooks.status_line.iter().map(|part| part(&context)).collect();
+        self.header = self.hooks.header.iter().map(|part| part(&context)).collect();
+        self.status_segments = self.hooks.status_segments
+            .iter()
+            .map(|(name, segment)| (name.clone(), segment(&context)))
+            .collect();
+        self.lines_key = Some(key);
+    }
+
+    /// Builds the indentation guides of a tree from the depth of each row.
+    /// Top-level rows have none.
+    fn tree_guides(depths: &[usize]) -> Vec<String> {
+        let mut guides = vec![String::new(); depths.len()];
+        // Whether a later row continues each level, walking the rows backwards
+        let mut continued: Vec<bool> = Vec::new();
+        for (index, &depth) in depths.iter().enumerate().rev() {
+            continued.resize(depth + 1, false);
+            if depth > 0 {
+                let guide = &mut guides[index];
+                for &open in &continued[1..depth] {
+                    guide.push_str(if open { "│ " } else { "  " });
+                }
+                guide.push_str(if continued[depth] { "├ " } else { "└ " });
             }
+            continued[depth] = true;
         }
+        guides
+    }
+
+    /// Output of the display modules for the row at `index`.
+    fn modules_at(&self, index: usize) -> Vec<StyledText> {
+        let info = self.entry_at(index);
+        self.display_modules
+            .iter()
+            .map(|column| (column.module)(&info))
+            .collect()
+    }
+
+    /// Whether a column has a title, shown above the listing.
+    pub fn has_titles(&self) -> bool {
+        self.display_modules.iter().any(|column| column.title.is_some())
+    }
+
+    /// Indices of the modules shown in `width` terminal columns. The columns
+    /// with the lowest priority are dropped until the rest fit, the
+    /// rightmost first.
+    pub fn visible_modules(&self, width: usize) -> Vec<usize> {
+        let mut visible: Vec<usize> = (0..self.max_widths.len()).collect();
+        // The selection and mark take two columns, and modules are separated
+        // by one
+        let mut used = 1 + self.max_widths.iter().map(|width| width + 1).sum::<usize>();
+        while used > width {
+            let dropped = visible
+                .iter()
+                .enumerate()
+                .filter_map(|(position, &i)| Some((self.display_modules[i].priority?, Reverse(i), position)))
+                .min();
+            let Some((_, Reverse(i), position)) = dropped else {
+                break;
+            };
+            used -= self.max_widths[i] + 1;
+            visible.remove(position);
+        }
+        visible
+    }
+
+    /// Builds the `Entry` handed to display modules for the row at `index`.
+    pub fn entry_at(&self, index: usize) -> Entry {
+        let path = &self.entries[index];
+        self.create_entry(path, self.get_display_name(path, index))
+    }
+
+    /// The branch of the repository holding `current_path`.
+    pub fn git_branch(&self) -> Option<&Branch> {
+        self.git.as_ref()?.branch.as_ref()
+    }
+
+    /// Git status of the entry at `index`, `None` when it is unchanged or
+    /// outside of a repository.
+    pub fn git_status(&self, index: usize) -> Option<GitStatus> {
+        self.git.as_ref()?.get(&self.entries[index])
     }
 
     fn create_entry(&self, entry: &PathBuf, display_name: String) -> Entry {
+        use std::os::unix::fs::PermissionsExt;
+        let metadata = std::fs::metadata(entry).ok();
+        let now = std::time::SystemTime::now();
         Entry {
             path: entry.to_path_buf(),
             name: display_name,
             is_dir: entry.is_dir(),
-            created: std::fs::metadata(entry)
-                .and_then(|meta| meta.created())
-                .unwrap_or_else(|_| std::time::SystemTime::now()),
+            created: metadata.as_ref()
+                .and_then(|meta| meta.created().ok())
+                .unwrap_or(now),
+            modified: metadata.as_ref()
+                .and_then(|meta| meta.modified().ok())
+                .unwrap_or(now),
             size: self.get_size(entry),
+            is_symlink: entry.is_symlink(),
+            mode: metadata.as_ref()
+                .map(|meta| meta.permissions().mode())
+                .unwrap_or(0),
+            git: self.git.as_ref().and_then(|git| git.get(entry)),
         }
     }
 
     fn get_display_name(&self, entry: &PathBuf, index: usize) -> String {
         if index == 0 {
-            "../".to_string()
-        } else if entry.is_dir() {
-            format!("{}/", entry.file_name().unwrap_or_default().to_string_lossy())
+            return "../".to_string();
+        }
+        let name = match self.listing {
+            Listing::Directory | Listing::Tree(_) => entry.file_name().unwrap_or_default().to_string_lossy(),
+            // Paths can come from anywhere, show them relative to where we are
+            Listing::Paths(_) | Listing::Results(_) | Listing::Recursive => {
+                entry.strip_prefix(&self.current_path).unwrap_or(entry).to_string_lossy()
+            }
+        };
+        let name = if entry.is_dir() {
+            format!("{}/", name)
         } else {
-            entry.file_name().unwrap_or_default().to_string_lossy().to_string()
+            name.to_string()
+        };
+        match self.lines.get(index) {
+            Some(Some(line)) => format!("{}:{}: {}", name, line.number, line.text),
+            _ => name,
         }
     }
     
@@ -93,3 +839,54 @@ impl AppState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A state whose modules have `priorities` and output up to `widths`.
+    fn state_with(priorities: &[Option<i64>], widths: &[usize]) -> AppState {
+        let modules = priorities
+            .iter()
+            .map(|&priority| Column { priority, ..Column::new(Box::new(|_| StyledText::default())) })
+            .collect();
+        let mut state = AppState::with_shared(
+            Rc::new(Config::default()),
+            Rc::default(),
+            Rc::new(modules),
+            Rc::default(),
+            Path::new("/"),
+            Listing::Directory,
+        );
+        state.max_widths = widths.to_vec();
+        state
+    }
+
+    #[test]
+    fn all_modules_shown_when_they_fit() {
+        let state = state_with(&[Some(1), Some(2), None], &[4, 4, 4]);
+        // Two columns for the selection and mark, plus a separator per module
+        assert_eq!(state.visible_modules(16), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn lowest_priority_dropped_first() {
+        let state = state_with(&[None, Some(1), Some(2)], &[4, 4, 4]);
+        assert_eq!(state.visible_modules(15), vec![0, 2]);
+        assert_eq!(state.visible_modules(11), vec![0, 2]);
+        assert_eq!(state.visible_modules(10), vec![0]);
+    }
+
+    #[test]
+    fn ties_drop_the_rightmost_first() {
+        let state = state_with(&[Some(1), Some(1), Some(1)], &[4, 4, 4]);
+        assert_eq!(state.visible_modules(15), vec![0, 1]);
+        assert_eq!(state.visible_modules(10), vec![0]);
+    }
+
+    #[test]
+    fn modules_without_priority_are_kept() {
+        let state = state_with(&[None, Some(1), None], &[10, 2, 10]);
+        assert_eq!(state.visible_modules(5), vec![0, 2]);
+    }
+}
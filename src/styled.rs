@@ -0,0 +1,245 @@
+use crossterm::style::{Attribute, Color, ContentStyle};
+use mlua::prelude::*;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+use crate::theme;
+
+/// Colors and attributes of a span. Unset colors are left to the row.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct SpanStyle {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub dim: bool,
+}
+
+impl SpanStyle {
+    /// Applies the span's colors and attributes over `base`. The colors are
+    /// only applied with `colors`, e.g. when the row isn't selected.
+    pub fn apply(&self, mut base: ContentStyle, colors: bool) -> ContentStyle {
+        if colors {
+            base.foreground_color = self.fg.or(base.foreground_color);
+            base.background_color = self.bg.or(base.background_color);
+        }
+        for (set, attribute) in [
+            (self.bold, Attribute::Bold),
+            (self.italic, Attribute::Italic),
+            (self.underline, Attribute::Underlined),
+            (self.dim, Attribute::Dim),
+        ] {
+            if set {
+                base.attributes.set(attribute);
+            }
+        }
+        base
+    }
+}
+
+#[derive(Clone)]
+pub struct Span {
+    pub text: String,
+    pub style: SpanStyle,
+}
+
+/// Output of a display module, made of differently styled spans.
+#[derive(Clone, Default)]
+pub struct StyledText {
+    pub spans: Vec<Span>,
+}
+
+impl StyledText {
+    pub fn new(spans: Vec<Span>) -> Self {
+        Self { spans }
+    }
+
+    /// The text of all the spans.
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|span| span.text.as_str()).collect()
+    }
+
+    /// Number of terminal columns taken by the text.
+    pub fn width(&self) -> usize {
+        self.spans.iter().map(|span| span.text.width()).sum()
+    }
+
+    pub fn is_blank(&self) -> bool {
+        self.spans.iter().all(|span| span.text.trim().is_empty())
+    }
+
+    /// Cuts the text to `width` columns, ending it with an ellipsis when it
+    /// doesn't fit.
+    pub fn truncate(&self, width: usize) -> Self {
+        if self.width() <= width {
+            return self.clone();
+        }
+        let mut spans: Vec<Span> = Vec::new();
+        let mut used = 0;
+        for (c, style) in self.chars() {
+            let w = c.width().unwrap_or(0);
+            if used + w >= width {
+                break;
+            }
+            used += w;
+            match spans.last_mut() {
+                Some(span) if span.style == style => span.text.push(c),
+                _ => spans.push(Span { text: c.to_string(), style }),
+            }
+        }
+        if width > 0 {
+            let style = spans.last().map_or_else(SpanStyle::default, |span| span.style);
+            spans.push(Span { text: "…".into(), style });
+        }
+        Self::new(spans)
+    }
+
+    /// Each character of the text, with the style of its span.
+    pub fn chars(&self) -> impl Iterator<Item = (char, SpanStyle)> + '_ {
+        self.spans
+            .iter()
+            .flat_map(|span| span.text.chars().map(move |c| (c, span.style)))
+    }
+}
+
+impl From<String> for StyledText {
+    fn from(text: String) -> Self {
+        Self::new(vec![Span { text, style: SpanStyle::default() }])
+    }
+}
+
+/// Modules return a string, a span such as `{ text = "M", fg = "red",
+/// bold = true }`, or a list of strings and spans.
+impl FromLua for StyledText {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        match value {
+            LuaValue::Nil => Ok(Self::default()),
+            LuaValue::Table(table) if table.contains_key("text")? => Ok(Self::new(vec![span_from_lua(&table)?])),
+            LuaValue::Table(table) => {
+                let mut spans = Vec::new();
+                for part in table.sequence_values::<LuaValue>() {
+                    spans.extend(Self::from_lua(part?, lua)?.spans);
+                }
+                Ok(Self::new(spans))
+            }
+            value => Ok(String::from_lua(value, lua)?.into()),
+        }
+    }
+}
+
+/// Spans are handed to Lua as a list of span tables, with the colors as
+/// `{ r, g, b }` tables.
+impl IntoLua for StyledText {
+    fn into_lua(self, lua: &Lua) -> LuaResult<LuaValue> {
+        let spans = lua.create_table()?;
+        for span in self.spans {
+            let table = lua.create_table()?;
+            table.set("text", span.text)?;
+            for (key, color) in [("fg", span.style.fg), ("bg", span.style.bg)] {
+                if let Some(Color::Rgb { r, g, b }) = color {
+                    let rgb = lua.create_table()?;
+                    rgb.set("r", r)?;
+                    rgb.set("g", g)?;
+                    rgb.set("b", b)?;
+                    table.set(key, rgb)?;
+                }
+            }
+            table.set("bold", span.style.bold)?;
+            table.set("italic", span.style.italic)?;
+            table.set("underline", span.style.underline)?;
+            table.set("dim", span.style.dim)?;
+            spans.push(table)?;
+        }
+        Ok(LuaValue::Table(spans))
+    }
+}
+
+fn span_from_lua(table: &LuaTable) -> LuaResult<Span> {
+    Ok(Span {
+        text: table.get("text")?,
+        style: SpanStyle {
+            fg: color_from_lua(table.get("fg")?)?,
+            bg: color_from_lua(table.get("bg")?)?,
+            bold: table.get::<Option<bool>>("bold")?.unwrap_or(false),
+            italic: table.get::<Option<bool>>("italic")?.unwrap_or(false),
+            underline: table.get::<Option<bool>>("underline")?.unwrap_or(false),
+            dim: table.get::<Option<bool>>("dim")?.unwrap_or(false),
+        },
+    })
+}
+
+/// Reads a color given as `{ r, g, b }` or by name, like `"red"` or
+/// `"dark_grey"`.
+fn color_from_lua(value: LuaValue) -> LuaResult<Option<Color>> {
+    match value {
+        LuaValue::Nil => Ok(None),
+        LuaValue::Table(rgb) => theme::to_rgb(&rgb).map(Some),
+        LuaValue::String(name) => {
+            let name = name.to_str()?;
+            Color::try_from(&*name)
+                .map(Some)
+                .map_err(|_| LuaError::RuntimeError(format!("Unknown color: {}", &*name)))
+        }
+        _ => Err(LuaError::RuntimeError("A color is a name or an { r, g, b } table".into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn styled(parts: &[(&str, SpanStyle)]) -> StyledText {
+        StyledText::new(parts.iter().map(|&(text, style)| Span { text: text.into(), style }).collect())
+    }
+
+    fn bold() -> SpanStyle {
+        SpanStyle { bold: true, ..SpanStyle::default() }
+    }
+
+    #[test]
+    fn width_counts_wide_characters_twice() {
+        let text = StyledText::from("ab漢字".to_string());
+        assert_eq!(text.width(), 6);
+        assert_eq!(styled(&[("ab", bold()), ("漢", SpanStyle::default())]).width(), 4);
+    }
+
+    #[test]
+    fn truncate_keeps_text_that_fits() {
+        let text = StyledText::from("abc".to_string());
+        assert_eq!(text.truncate(3).text(), "abc");
+        assert_eq!(text.truncate(10).text(), "abc");
+    }
+
+    #[test]
+    fn truncate_never_splits_a_wide_character() {
+        let text = StyledText::from("ab漢字".to_string());
+        // The ellipsis takes the column the second half of 漢 would need
+        assert_eq!(text.truncate(4).text(), "ab…");
+        assert_eq!(text.truncate(4).width(), 3);
+        assert_eq!(text.truncate(5).text(), "ab漢…");
+        assert_eq!(text.truncate(5).width(), 5);
+    }
+
+    #[test]
+    fn truncate_to_tiny_widths() {
+        let text = StyledText::from("abc".to_string());
+        assert!(text.truncate(0).spans.is_empty());
+        assert_eq!(text.truncate(1).text(), "…");
+        assert_eq!(StyledText::from("漢字".to_string()).truncate(1).text(), "…");
+    }
+
+    #[test]
+    fn truncate_keeps_styles_across_spans() {
+        let text = styled(&[("ab", SpanStyle::default()), ("cd", bold()), ("ef", SpanStyle::default())]);
+        let cut = text.truncate(4);
+        assert_eq!(cut.text(), "abc…");
+        assert_eq!(cut.spans.len(), 3);
+        assert!(cut.spans[0].style == SpanStyle::default());
+        // The ellipsis takes the style of the last kept character
+        assert!(cut.spans[1].style == bold());
+        assert!(cut.spans[2].style == bold());
+
+        let cut = text.truncate(3);
+        assert_eq!(cut.text(), "ab…");
+        assert!(cut.spans.iter().all(|span| span.style == SpanStyle::default()));
+    }
+}
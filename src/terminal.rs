@@ -1,6 +1,10 @@
 use crossterm::{cursor, execute, queue, style, terminal::{self, ClearType}, style::{Stylize, Color}, event};
 use std::io::Write;
-use crossterm::style::StyledContent;
+use crossterm::style::{ContentStyle, StyledContent};
+use unicode_width::UnicodeWidthChar;
+use crate::git::GitStatus;
+use crate::lua::Align;
+use crate::styled::StyledText;
 use crate::theme::Theme;
 
 pub fn init<W: Write>(writer: &mut W) {
@@ -13,13 +17,25 @@ pub fn cleanup<W: Write>(writer: &mut W) {
     terminal::disable_raw_mode().unwrap();
 }
 
+/// Hands the terminal over to a child process, such as an editor.
+pub fn suspend<W: Write>(writer: &mut W) {
+    execute!(writer, terminal::LeaveAlternateScreen, cursor::Show, event::DisableMouseCapture).unwrap();
+    terminal::disable_raw_mode().unwrap();
+}
+
+/// Takes the terminal back after `suspend`.
+pub fn resume<W: Write>(writer: &mut W) {
+    terminal::enable_raw_mode().unwrap();
+    execute!(writer, terminal::EnterAlternateScreen, cursor::Hide, event::EnableMouseCapture).unwrap();
+}
+
 pub fn clear_screen<W: Write>(writer: &mut W) {
     execute!(writer, terminal::Clear(ClearType::All)).unwrap();
 }
 
+/// The terminal size, or 80x24 when there is no terminal to ask.
 pub fn size_of_terminal() -> (u16, u16) {
-    let (width, height) = terminal::size().unwrap();
-    (width, height)
+    terminal::size().unwrap_or((80, 24))
 }
 
 pub fn display_prompt<W: Write>(writer: &mut W, prefix: &str, query: &str, row: u16) {
@@ -28,80 +44,183 @@ pub fn display_prompt<W: Write>(writer: &mut W, prefix: &str, query: &str, row:
     queue!(writer, cursor::Show, cursor::EnableBlinking).unwrap();
 }
 
+/// Horizontal span of the screen a listing is drawn in.
+#[derive(Clone, Copy)]
+pub struct Area {
+    pub x: u16,
+    pub width: u16,
+}
+
+/// The output of a module, as laid out in its column.
+pub struct Cell<'a> {
+    pub text: &'a StyledText,
+    pub width: usize,
+    pub align: Align,
+}
+
+/// How a row of a listing is highlighted.
+#[derive(Default)]
+pub struct RowState<'a> {
+    pub selected: bool,
+    pub is_match: bool,
+    /// Module showing the name, and the characters of it matched by the
+    /// search. The whole row is highlighted for matches without one.
+    pub highlighted: Option<(usize, Vec<usize>)>,
+    pub marked: bool,
+    /// Indentation guides of a tree listing, drawn at the start of the row
+    pub guide: &'a str,
+    /// Colors the row when it isn't selected
+    pub git: Option<GitStatus>,
+}
+
+/// Index of the module whose column makes room for the indentation guides.
+/// The guides push the narrow leading modules, like icons, along with the
+/// first wider one, which is usually the name.
+pub fn guided_module(max_widths: &[usize]) -> usize {
+    max_widths
+        .iter()
+        .position(|&width| width > 2)
+        .unwrap_or(max_widths.len().saturating_sub(1))
+}
+
 pub fn display_entry<W: Write>(
     writer: &mut W,
-    display_modules: Vec<String>,
+    cells: Vec<Cell>,
+    area: Area,
     row: u16,
-    selected: bool,
-    max_width: Vec<usize>,
-    is_match: bool,
+    row_state: RowState,
     theme: &Theme,
 ) {
-    let mut styled_modules: Vec<StyledContent<String>> = Vec::new();
+    let fg = row_state.git.map_or(theme.fg, |status| theme.git.get(status));
+    let whole_row_match = row_state.is_match && row_state.highlighted.is_none();
 
-    for module in display_modules {
-        styled_modules.push(module.with(theme.fg));
-    }
-
-    if selected {
-        queue!(writer, cursor::MoveTo(0, row), style::Print(">")).unwrap();
-        styled_modules = styled_modules.into_iter().map(|module| {
-            if !module.content().chars().all(|c| c == ' ') {
-                module.with(theme.selected_fg).on(theme.selected_bg)
-            } else {
-                module
-            }
-        }).collect();
+    if row_state.selected {
+        queue!(writer, cursor::MoveTo(area.x, row), style::Print(">")).unwrap();
     } else {
         queue!(writer, style::ResetColor).unwrap();
-        styled_modules = styled_modules.into_iter().map(|module| {
-            module.with(theme.fg)
-        }).collect();
     }
 
-    if is_match {
-        styled_modules = styled_modules.into_iter().map(|module| {
-            module.with(theme.highlight)
-        }).collect();
-    } else {
+    if row_state.marked {
+        queue!(writer, cursor::MoveTo(area.x + 1, row), style::PrintStyledContent("*".with(theme.highlight))).unwrap();
+    }
+
+    if !whole_row_match {
         queue!(writer, cursor::Hide).unwrap();
     }
 
+    let end = (area.x + area.width) as usize;
+    let mut position = area.x as usize + 2;
+    let guide = fit_to_width(row_state.guide, end.saturating_sub(position));
+    let guide_width = guide.chars().count();
+    if !guide.is_empty() {
+        queue!(writer, cursor::MoveTo(position as u16, row), style::PrintStyledContent(guide.with(theme.fg))).unwrap();
+    }
+    let widths: Vec<usize> = cells.iter().map(|cell| cell.width).collect();
+    let guided_module = guided_module(&widths);
+    for (i, cell) in cells.iter().enumerate() {
+        let module_position = if i <= guided_module { position + guide_width } else { position };
+        if module_position >= end {
+            break;
+        }
+        // The guides take their room from the column of the guided module
+        let width = if i == guided_module { cell.width.saturating_sub(guide_width) } else { cell.width };
+        let module = cell.text.truncate(width.min(end - module_position));
+        let (padding, _) = cell.align.padding(width.saturating_sub(module.width()));
+        let module_position = (module_position + padding).min(end - module.width());
+        // The row's colors, which the spans of the module are drawn over
+        let mut style = ContentStyle::new();
+        style.foreground_color = Some(fg);
+        if row_state.selected && !module.is_blank() {
+            style.foreground_color = Some(theme.selected_fg);
+            style.background_color = Some(theme.selected_bg);
+        }
+        if whole_row_match {
+            style.foreground_color = Some(theme.highlight);
+        }
+        let positions = match &row_state.highlighted {
+            Some((highlighted, positions)) if *highlighted == i => positions.as_slice(),
+            _ => &[],
+        };
+        queue!(writer, cursor::MoveTo(module_position as u16, row)).unwrap();
+        let span_colors = !row_state.selected && !whole_row_match;
+        print_module(writer, &module, style, span_colors, positions, theme.highlight);
+        position += cell.width + 1;
+    }
+}
+
+/// Prints `module` with its spans drawn over `base`, taking their colors
+/// only with `span_colors`. The characters at `positions` are printed in
+/// `color`.
+fn print_module<W: Write>(
+    writer: &mut W,
+    module: &StyledText,
+    base: ContentStyle,
+    span_colors: bool,
+    positions: &[usize],
+    color: Color,
+) {
+    // Print runs of characters sharing a style
+    let mut run = String::new();
+    let mut run_style = base;
+    for (i, (c, span_style)) in module.chars().enumerate() {
+        let mut style = span_style.apply(base, span_colors);
+        if positions.contains(&i) {
+            style.foreground_color = Some(color);
+            style.attributes.set(style::Attribute::Bold);
+        }
+        if style != run_style && !run.is_empty() {
+            queue!(writer, style::PrintStyledContent(StyledContent::new(run_style, std::mem::take(&mut run)))).unwrap();
+        }
+        run_style = style;
+        run.push(c);
+    }
+    if !run.is_empty() {
+        queue!(writer, style::PrintStyledContent(StyledContent::new(run_style, run))).unwrap();
+    }
+}
 
-    let mut position = 2;
-    for (i, module) in styled_modules.iter().enumerate() {
-        let module_width = max_width[i];
-        queue!(writer, cursor::MoveTo((position) as u16, row), style::PrintStyledContent(module.clone())).unwrap();
-        position += module_width as u16 + 1;
+/// Cuts `text` so that it takes at most `width` columns.
+pub fn fit_to_width(text: &str, width: usize) -> String {
+    let mut result = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w > width {
+            break;
+        }
+        used += w;
+        result.push(c);
     }
+    result
 }
 
 pub fn flush<W: Write>(writer: &mut W) {
     writer.flush().unwrap();
 }
 
-pub fn display_delete_warning<W: Write>(writer: &mut W, row: usize) {
+pub fn display_delete_warning<W: Write>(writer: &mut W, column: u16, row: usize) {
     let warning = "Press d again to delete";
     let styled_warning = warning.with(Color::Rgb{
         r: 243,
         g: 139,
         b: 168
     }).italic();
-    queue!(writer, cursor::MoveTo(50, row as u16), style::PrintStyledContent(styled_warning)).unwrap();
+    queue!(writer, cursor::MoveTo(column, row as u16), style::PrintStyledContent(styled_warning)).unwrap();
 }
 
-// Display a line at the right of the screen
-pub fn display_navbar<W: Write>(writer: &mut W, start_viewport: usize, end_viewport: usize, total_entries: usize) {
+// Display a line in `column`, next to the listing starting at row `top`
+pub fn display_navbar<W: Write>(writer: &mut W, column: u16, top: usize, start_viewport: usize, end_viewport: usize, total_entries: usize) {
     if end_viewport >= total_entries {
         return;
     }
 
-    let (width, height) = terminal::size().unwrap();
+    let (_, height) = terminal::size().unwrap();
+    let height = (height as usize).saturating_sub(top);
 
     let start = ((start_viewport as f64 / total_entries as f64) * height as f64) as usize;
     let end = ((end_viewport as f64 / total_entries as f64) * height as f64) as usize;
 
     for i in start..end {
-        queue!(writer, cursor::MoveTo(width - 1, i as u16), style::Print("┃")).unwrap();
+        queue!(writer, cursor::MoveTo(column, (top + i) as u16), style::Print("┃")).unwrap();
     }
 }
\ No newline at end of file
@@ -1,6 +1,7 @@
 use crossterm::style::Color;
 use mlua::prelude::*;
 use mlua::Table;
+use crate::git::GitStatus;
 
 #[derive(Clone)]
 pub struct Theme {
@@ -9,6 +10,69 @@ pub struct Theme {
     pub selected_fg: Color,
     pub selected_bg: Color,
     pub highlight: Color,
+    pub git: GitColors,
+}
+
+/// Colors of the entries with a git status.
+#[derive(Clone)]
+pub struct GitColors {
+    pub modified: Color,
+    pub staged: Color,
+    pub untracked: Color,
+    pub ignored: Color,
+    pub conflicted: Color,
+}
+
+impl GitColors {
+    pub fn get(&self, status: GitStatus) -> Color {
+        match status {
+            GitStatus::Modified => self.modified,
+            GitStatus::Staged => self.staged,
+            GitStatus::Untracked => self.untracked,
+            GitStatus::Ignored => self.ignored,
+            GitStatus::Conflicted => self.conflicted,
+        }
+    }
+
+    /// Reads the colors set in `table`, keeping the default ones for the
+    /// others.
+    fn from_lua(table: Option<LuaTable>) -> LuaResult<Self> {
+        let mut colors = Self::default();
+        let Some(table) = table else {
+            return Ok(colors);
+        };
+        for (key, color) in [
+            ("modified", &mut colors.modified),
+            ("staged", &mut colors.staged),
+            ("untracked", &mut colors.untracked),
+            ("ignored", &mut colors.ignored),
+            ("conflicted", &mut colors.conflicted),
+        ] {
+            if let Some(rgb) = table.get::<Option<LuaTable>>(key)? {
+                *color = to_rgb(&rgb)?;
+            }
+        }
+        Ok(colors)
+    }
+}
+
+impl Default for GitColors {
+    fn default() -> Self {
+        GitColors {
+            modified: Color::Yellow,
+            staged: Color::Green,
+            untracked: Color::Cyan,
+            ignored: Color::DarkGrey,
+            conflicted: Color::Red,
+        }
+    }
+}
+
+pub fn to_rgb(t: &LuaTable) -> LuaResult<Color> {
+    let r = t.get::<_>("r")?;
+    let g = t.get::<_>("g")?;
+    let b = t.get::<_>("b")?;
+    Ok(Color::Rgb { r, g, b })
 }
 
 impl Theme {
@@ -18,19 +82,13 @@ impl Theme {
         let selected: Table = table.get::<_>("selected")?;
         let highlight = table.get::<_>("highlight")?;
 
-        fn to_rgb(t: &LuaTable) -> LuaResult<Color> {
-            let r = t.get::<_>("r")?;
-            let g = t.get::<_>("g")?;
-            let b = t.get::<_>("b")?;
-            Ok(Color::Rgb { r, g, b })
-        }
-
         Ok(Theme {
             fg: to_rgb(&fg)?,
             bg: to_rgb(&bg)?,
             selected_fg: to_rgb(&selected.get::<_>("fg")?)?,
             selected_bg: to_rgb(&selected.get::<_>("bg")?)?,
             highlight: to_rgb(&highlight)?,
+            git: GitColors::from_lua(table.get("git")?)?,
         })
     }
     
@@ -41,6 +99,7 @@ impl Theme {
             selected_fg: Color::Black,
             selected_bg: Color::White,
             highlight: Color::Green,
+            git: GitColors::default(),
         }
     }
 }
\ No newline at end of file
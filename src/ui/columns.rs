@@ -0,0 +1,66 @@
+use std::path::PathBuf;
+use std::rc::Rc;
+use crate::error::Result;
+use crate::lua::Column;
+use crate::state::AppState;
+
+/// The listings next to the active tab in the columns view: its parent
+/// directory on the left, and the selected directory on the right.
+pub struct MillerColumns {
+    pub parent: Option<AppState>,
+    pub child: Option<AppState>,
+    /// Directory whose parent the left column was loaded for
+    parent_of: Option<PathBuf>,
+    /// Selected directory the right column was loaded for
+    child_of: Option<PathBuf>,
+}
+
+impl MillerColumns {
+    pub fn new() -> Self {
+        Self {
+            parent: None,
+            child: None,
+            parent_of: None,
+            child_of: None,
+        }
+    }
+
+    /// Reloads the parent column when the directory of `state` changed, and
+    /// the child column when its selection changed. Columns that are kept are
+    /// refreshed when another tab changed files.
+    pub fn update(&mut self, state: &AppState, modules: &Rc<Vec<Column>>) -> Result<()> {
+        // Directories that can't be read are shown without contents
+        if self.parent_of.as_ref() != Some(&state.current_path) {
+            self.parent = state.current_path.parent().and_then(|parent| {
+                let mut column = state.with_modules(parent, modules.clone()).ok()?;
+                column.select_path(&state.current_path);
+                Some(column)
+            });
+            self.parent_of = Some(state.current_path.clone());
+        } else if let Some(column) = self.parent.as_mut().filter(|column| column.is_outdated()) {
+            column.refresh()?;
+        }
+
+        let selected = match state.selected {
+            0 => None,
+            i => state.entries.get(i).filter(|path| path.is_dir()).cloned(),
+        };
+        if self.child_of != selected {
+            self.child = selected
+                .as_ref()
+                .and_then(|path| state.with_modules(path, modules.clone()).ok());
+            self.child_of = selected;
+        } else if let Some(column) = self.child.as_mut().filter(|column| column.is_outdated()) {
+            column.refresh()?;
+        }
+        Ok(())
+    }
+
+    /// Fits the viewports of the columns to `height` rows.
+    pub fn resize(&mut self, height: usize) {
+        for column in self.parent.iter_mut().chain(self.child.iter_mut()) {
+            column.viewport.resize(height);
+            column.viewport.update(column.selected, column.entries.len());
+        }
+    }
+}
@@ -1,69 +1,200 @@
 use std::io::Write;
-use crossterm::{cursor, queue};
+use crossterm::{cursor, queue, style};
+use crossterm::style::Stylize;
 use crate::state::AppState;
 use crate::theme::Theme;
-use crate::terminal;
+use crate::styled::{Span, SpanStyle, StyledText};
+use crate::terminal::{self, Area, Cell, RowState};
 use crossterm::terminal::{Clear, ClearType};
 
+mod columns;
+mod status;
+
+pub use columns::MillerColumns;
+
+/// The window of entries visible in a listing.
+pub struct Viewport {
+    start: usize,
+    size: usize,
+}
+
+impl Viewport {
+    /// Starts with a single row; the renderer resizes it to the list height
+    /// before the first draw.
+    pub fn new() -> Self {
+        Self { start: 0, size: 1 }
+    }
+
+    pub fn resize(&mut self, size: usize) {
+        self.size = size.max(1);
+    }
+
+    pub fn update(&mut self, selected: usize, total_entries: usize) {
+        // Adjust viewport when selection is out of view
+        if selected >= self.start + self.size {
+            self.start = selected - self.size + 1;
+        } else if selected < self.start {
+            self.start = selected;
+        }
+
+        // Ensure start doesn't exceed possible bounds
+        if self.start > total_entries.saturating_sub(self.size) {
+            self.start = total_entries.saturating_sub(self.size);
+        }
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn reset(&mut self) {
+        self.start = 0;
+    }
+
+    pub fn scroll_up(&mut self) {
+        if self.start > 0 {
+            self.start = self.start.saturating_sub(1);
+        }
+    }
+
+    pub fn scroll_down(&mut self, total_entries: usize) {
+        let max_start = total_entries.saturating_sub(self.size);
+        if self.start < max_start {
+            self.start += 1;
+        }
+    }
+}
+
+/// How the listings are arranged on screen.
+#[derive(Clone, Copy, PartialEq)]
+pub enum View {
+    Single,
+    /// The active tab next to another one
+    DualPane,
+    /// The active tab between its parent and the selected directory
+    Columns,
+}
+
 pub struct Renderer {
-    viewport_start: usize,
-    viewport_size: usize,
     theme: Theme,
+    show_tab_bar: bool,
+    /// Whether a header was set from Lua, drawn below the tab bar
+    show_header: bool,
+    /// Whether the columns have titles, drawn above the listings
+    show_titles: bool,
+    view: View,
 }
 
 impl Renderer {
     pub fn new(theme: Theme) -> Self {
-        let (_, height) = terminal::size_of_terminal();
         Self {
-            viewport_start: 0,
-            viewport_size: height as usize - 2,
             theme,
+            show_tab_bar: false,
+            show_header: false,
+            show_titles: false,
+            view: View::Single,
         }
     }
 
-    pub fn update_viewport(&mut self, selected: usize, total_entries: usize) {
-        let terminal_height = terminal::size_of_terminal().1 as usize;
-        self.viewport_size = terminal_height - 2;
+    /// The tab bar is only shown when there is more than one tab.
+    pub fn set_layout(&mut self, tab_count: usize, view: View, show_header: bool, show_titles: bool) {
+        self.show_tab_bar = tab_count > 1;
+        self.show_header = show_header;
+        self.show_titles = show_titles;
+        self.view = view;
+    }
 
-        // Adjust viewport when selection is out of view
-        if selected >= self.viewport_start + self.viewport_size {
-            self.viewport_start = selected - self.viewport_size + 1;
-        } else if selected < self.viewport_start {
-            self.viewport_start = selected;
-        }
+    /// First terminal row below the tab bar and the header.
+    fn content_top(&self) -> usize {
+        self.show_tab_bar as usize + self.show_header as usize
+    }
 
-        // Ensure viewport_start doesn't exceed possible bounds
-        if self.viewport_start > total_entries.saturating_sub(self.viewport_size) {
-            self.viewport_start = total_entries.saturating_sub(self.viewport_size);
-        }
+    /// First terminal row of the listing.
+    pub fn list_top(&self) -> usize {
+        // In dual-pane mode each pane has a header with its path
+        self.content_top() + (self.view == View::DualPane) as usize + self.show_titles as usize
     }
-    
-    pub fn get_viewport_start(&self) -> usize {
-        self.viewport_start
+
+    /// Number of rows available to the listing.
+    pub fn list_height(&self) -> usize {
+        let terminal_height = terminal::size_of_terminal().1 as usize;
+        terminal_height.saturating_sub(2 + self.list_top())
     }
-    
-    pub fn get_viewport_size(&self) -> usize {
-        self.viewport_size
+
+    /// Areas of the left and right panes in dual-pane mode.
+    fn pane_areas(&self) -> (Area, Area) {
+        let width = terminal::size_of_terminal().0;
+        let half = width / 2;
+        (
+            Area { x: 0, width: half },
+            Area { x: half + 1, width: width.saturating_sub(half + 1) },
+        )
     }
-    
-    pub fn reset_viewport(&mut self) {
-        self.viewport_start = 0;
+
+    /// Areas of the parent, current and child columns in the columns view.
+    fn column_areas(&self) -> (Area, Area, Area) {
+        let width = terminal::size_of_terminal().0;
+        let parent = width / 5;
+        let current = width * 2 / 5;
+        (
+            Area { x: 0, width: parent },
+            Area { x: parent + 1, width: current },
+            Area { x: parent + current + 2, width: width.saturating_sub(parent + current + 2) },
+        )
     }
 
-    pub fn scroll_up(&mut self) {
-        if self.viewport_start > 0 {
-            self.viewport_start = self.viewport_start.saturating_sub(1);
-        }
+    /// Whether `column` lies in the right pane in dual-pane mode.
+    pub fn is_right_pane(&self, column: u16) -> bool {
+        self.view == View::DualPane && column > self.pane_areas().0.width
     }
 
-    pub fn scroll_down(&mut self, total_entries: usize) {
-        let max_start = total_entries.saturating_sub(self.viewport_size);
-        if self.viewport_start < max_start {
-            self.viewport_start += 1;
+    /// Index of the display module whose title is at `column` and `row`, on
+    /// the row of titles above the active listing.
+    pub fn title_at(&self, state: &AppState, column: u16, row: u16) -> Option<usize> {
+        if !self.show_titles || row as usize + 1 != self.list_top() {
+            return None;
+        }
+        let area = match self.view {
+            View::Single => Area { x: 0, width: terminal::size_of_terminal().0 },
+            View::DualPane if self.is_right_pane(column) => self.pane_areas().1,
+            View::DualPane => self.pane_areas().0,
+            View::Columns => self.column_areas().1,
+        };
+        // Lay the columns out like `draw_titles`, leaving out the scrollbar
+        let mut position = area.x as usize + 2;
+        for i in state.visible_modules(area.width.saturating_sub(1) as usize) {
+            let end = position + state.max_widths[i];
+            if (position..end).contains(&(column as usize)) {
+                return state.display_modules[i].title.as_ref().map(|_| i);
+            }
+            position = end + 1;
         }
+        None
     }
 
-    pub fn render<W: Write>(&self, writer: &mut W, state: &AppState) {
+    /// Whether `column` lies outside the listing of the active tab, i.e. in a
+    /// side column of the columns view.
+    pub fn is_outside_list(&self, column: u16) -> bool {
+        let (_, current, _) = self.column_areas();
+        self.view == View::Columns && !(current.x..current.x + current.width).contains(&column)
+    }
+
+    /// Draws the active tab, and in dual-pane mode the tab shown in the other
+    /// pane next to it. The tab with the lower index is on the left. In the
+    /// columns view, the parent and child columns are drawn around it.
+    pub fn render<W: Write>(
+        &self,
+        writer: &mut W,
+        tabs: &[AppState],
+        active: usize,
+        other: Option<usize>,
+        columns: Option<&MillerColumns>,
+    ) {
+        let state = &tabs[active];
         queue!(
             writer,
             cursor::Hide,
@@ -71,13 +202,44 @@ impl Renderer {
             cursor::MoveTo(0, 0),
         ).unwrap();
 
-        let viewport_end = (self.viewport_start + self.viewport_size).min(state.entries.len());
-        
-        // Render entries
-        for (display_row, i) in (self.viewport_start..viewport_end).enumerate() {
-            self.draw_row(writer, state, i, display_row as u16);
+        if self.show_tab_bar {
+            self.draw_tab_bar(writer, tabs, active);
+        }
+        if self.show_header {
+            let header = terminal::fit_to_width(&state.header.join("  "), terminal::size_of_terminal().0 as usize);
+            let row = self.show_tab_bar as u16;
+            queue!(writer, cursor::MoveTo(0, row), style::PrintStyledContent(header.with(self.theme.fg))).unwrap();
+        }
+
+        match (other, columns) {
+            (Some(other), _) => {
+                let (left, right) = self.pane_areas();
+                let (left_tab, right_tab) = if active < other { (active, other) } else { (other, active) };
+                self.draw_listing(writer, &tabs[left_tab], left, left_tab == active);
+                self.draw_listing(writer, &tabs[right_tab], right, right_tab == active);
+                self.draw_separator(writer, left.width);
+            }
+            (None, Some(columns)) => {
+                let (parent, current, child) = self.column_areas();
+                // The parent column shows where the current directory is
+                if let Some(column) = &columns.parent {
+                    self.draw_listing(writer, column, parent, true);
+                }
+                self.draw_listing(writer, state, current, true);
+                if let Some(column) = &columns.child {
+                    self.draw_listing(writer, column, child, false);
+                }
+                self.draw_separator(writer, parent.width);
+                self.draw_separator(writer, current.x + current.width);
+            }
+            (None, None) => {
+                let width = terminal::size_of_terminal().0;
+                self.draw_listing(writer, state, Area { x: 0, width }, true);
+            }
         }
 
+        self.draw_status_line(writer, state);
+
         // Render prompt if active
         if state.prompt.is_active() {
             terminal::display_prompt(
@@ -88,15 +250,130 @@ impl Renderer {
             );
         }
 
+        writer.flush().unwrap();
+    }
+
+    /// Draws the status bar above the prompt, with the segments configured
+    /// for each side. The status line set from Lua replaces the left side,
+    /// and a message replaces it until the next key press.
+    fn draw_status_line<W: Write>(&self, writer: &mut W, state: &AppState) {
+        let (width, height) = terminal::size_of_terminal();
+        let row = height.saturating_sub(2);
+
+        let right = self.segments(&state.config.status_right, state);
+        let right_width = unicode_width::UnicodeWidthStr::width(right.as_str()) as u16;
+        let right_column = width.saturating_sub(right_width + 1);
+        queue!(writer, cursor::MoveTo(right_column, row), style::PrintStyledContent(right.with(self.theme.fg))).unwrap();
+
+        let left = if let Some(message) = &state.message {
+            message.clone()
+        } else if !state.status_line.is_empty() {
+            state.status_line.join("  ")
+        } else {
+            self.segments(&state.config.status_left, state)
+        };
+        let left = terminal::fit_to_width(&left, right_column.saturating_sub(1) as usize);
+        queue!(
+            writer,
+            cursor::MoveTo(0, row),
+            style::PrintStyledContent(left.with(self.theme.highlight)),
+        ).unwrap();
+    }
+
+    /// Joins the non-empty segments named in `names`, built-in or added from
+    /// Lua. Unknown names are skipped.
+    fn segments(&self, names: &[String], state: &AppState) -> String {
+        let mut segments = Vec::new();
+        for name in names {
+            let text = status::builtin_segment(name, state)
+                .or_else(|| state.status_segments.get(name).cloned());
+            segments.extend(text.filter(|text| !text.is_empty()));
+        }
+        segments.join("  ")
+    }
+
+    fn draw_separator<W: Write>(&self, writer: &mut W, column: u16) {
+        for row in self.content_top()..terminal::size_of_terminal().1 as usize - 1 {
+            queue!(writer, cursor::MoveTo(column, row as u16), style::Print("│")).unwrap();
+        }
+    }
+
+    fn draw_tab_bar<W: Write>(&self, writer: &mut W, tabs: &[AppState], active: usize) {
+        let mut column = 0;
+        for (i, tab) in tabs.iter().enumerate() {
+            let name = tab.current_path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| tab.current_path.display().to_string());
+            let label = format!(" {}:{} ", i + 1, name);
+            let styled = if i == active {
+                label.clone().with(self.theme.selected_fg).on(self.theme.selected_bg)
+            } else {
+                label.clone().with(self.theme.fg)
+            };
+            queue!(writer, cursor::MoveTo(column, 0), style::PrintStyledContent(styled)).unwrap();
+            column += unicode_width::UnicodeWidthStr::width(label.as_str()) as u16 + 1;
+        }
+    }
+
+    fn draw_listing<W: Write>(&self, writer: &mut W, state: &AppState, area: Area, is_active: bool) {
+        let mut top = self.content_top();
+        if self.view == View::DualPane {
+            let path = terminal::fit_to_width(&state.current_path.display().to_string(), area.width as usize);
+            let color = if is_active { self.theme.highlight } else { self.theme.fg };
+            queue!(writer, cursor::MoveTo(area.x, top as u16), style::PrintStyledContent(path.with(color))).unwrap();
+            top += 1;
+        }
+
+        // Keep the last column for the scrollbar
+        let list_area = Area { x: area.x, width: area.width.saturating_sub(1) };
+        if self.show_titles {
+            self.draw_titles(writer, state, list_area, top as u16);
+            top += 1;
+        }
+
+        let viewport_start = state.viewport.start();
+        let viewport_end = (viewport_start + state.viewport.size()).min(state.entries.len());
+
+        // Render entries
+        for (display_row, i) in (viewport_start..viewport_end).enumerate() {
+            self.draw_row(writer, state, i, list_area, (top + display_row) as u16, is_active);
+        }
+
         // Render scrollbar
         terminal::display_navbar(
             writer,
-            self.viewport_start,
+            area.x + area.width.saturating_sub(1),
+            top,
+            viewport_start,
             viewport_end,
             state.entries.len()
         );
+    }
 
-        writer.flush().unwrap();
+    /// Draws the titles of the columns of `state`, aligned with its rows.
+    fn draw_titles<W: Write>(&self, writer: &mut W, state: &AppState, area: Area, row: u16) {
+        let titles: Vec<StyledText> = state.display_modules
+            .iter()
+            .map(|column| {
+                let text = column.title.clone().unwrap_or_default();
+                StyledText::new(vec![Span { text, style: SpanStyle { bold: true, ..SpanStyle::default() } }])
+            })
+            .collect();
+        let visible = state.visible_modules(area.width as usize);
+        terminal::display_entry(writer, Self::cells(state, &titles, &visible), area, row, RowState::default(), &self.theme);
+    }
+
+    /// Lays out the output of the `visible` modules of `state`.
+    fn cells<'a>(state: &AppState, modules: &'a [StyledText], visible: &[usize]) -> Vec<Cell<'a>> {
+        visible
+            .iter()
+            .map(|&i| Cell {
+                text: &modules[i],
+                width: state.max_widths[i],
+                align: state.display_modules[i].align,
+            })
+            .collect()
     }
 
     fn draw_row<W: Write>(
@@ -104,25 +381,39 @@ impl Renderer {
         writer: &mut W,
         state: &AppState,
         idx: usize,
+        area: Area,
         row: u16,
+        is_active: bool,
     ) {
-        let selected = idx == state.selected;
-        let is_match = state.prompt.is_match(idx);
         let modules = &state.modules_cache[idx];
+        // Find the matched characters of the file name in the module showing it
+        let highlighted = state.prompt.match_positions(idx).and_then(|positions| {
+            let name = state.entries[idx].file_name()?.to_string_lossy();
+            modules.iter().enumerate().find_map(|(module, content)| {
+                let content = content.text();
+                let offset = content[..content.rfind(name.as_ref())?].chars().count();
+                Some((module, positions.iter().map(|position| position + offset).collect()))
+            })
+        });
+        // Point at the module among the visible ones
+        let visible = state.visible_modules(area.width as usize);
+        let highlighted = highlighted.and_then(|(module, positions)| {
+            Some((visible.iter().position(|&i| i == module)?, positions))
+        });
+        let row_state = RowState {
+            selected: is_active && idx == state.selected,
+            is_match: state.prompt.is_match(idx),
+            highlighted,
+            marked: state.marked.contains(&state.entries[idx]),
+            guide: state.guides.get(idx).map_or("", String::as_str),
+            git: state.git_status(idx),
+        };
 
-        terminal::display_entry(
-            writer,
-            modules.to_vec(),
-            row,
-            selected,
-            state.max_widths.clone(),
-            is_match,
-            &self.theme,
-        );
+        terminal::display_entry(writer, Self::cells(state, modules, &visible), area, row, row_state, &self.theme);
 
         if let Some(d) = state.delete_mode {
             if d == idx {
-                terminal::display_delete_warning(writer, idx);
+                terminal::display_delete_warning(writer, area.x + 50, row as usize);
             }
         }
     }
@@ -0,0 +1,158 @@
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use crate::lua::ColumnSort;
+use crate::matcher::MatchMode;
+use crate::state::AppState;
+
+/// Text of the built-in status bar segment `name`, empty when there is
+/// nothing to show, and `None` when there is no such segment.
+pub fn builtin_segment(name: &str, state: &AppState) -> Option<String> {
+    let text = match name {
+        "path" => breadcrumbs(&state.current_path),
+        "entries" => format!("{} entries", state.entries.len() - 1),
+        "selection" => selection(state),
+        "info" => selected_info(state),
+        "filter" => filter(state),
+        "git" => git_branch(state),
+        "hidden" => hidden(state),
+        "sort" => sort(state),
+        "free" => free_space(&state.current_path)
+            .map(|free| format!("{} free", human_size(free)))
+            .unwrap_or_default(),
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// The directories leading to `path`, starting from `~` inside the home
+/// directory.
+fn breadcrumbs(path: &Path) -> String {
+    let home = dirs::home_dir();
+    let (start, rest) = match home.as_deref().and_then(|home| path.strip_prefix(home).ok()) {
+        Some(rest) => ("~", rest),
+        None => ("/", path.strip_prefix("/").unwrap_or(path)),
+    };
+    let mut crumbs = vec![start.into()];
+    crumbs.extend(rest.iter().map(|part| part.to_string_lossy()));
+    crumbs.join(" › ")
+}
+
+/// Number and total size of the marked entries.
+fn selection(state: &AppState) -> String {
+    if state.marked.is_empty() {
+        return String::new();
+    }
+    let size: u64 = state.marked
+        .iter()
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .filter(|meta| !meta.is_dir())
+        .map(|meta| meta.len())
+        .sum();
+    format!("{} marked, {}", state.marked.len(), human_size(size))
+}
+
+/// The sort order, or the title of the column the listing is sorted by.
+fn sort(state: &AppState) -> String {
+    match state.sort_column.map(|index| &state.display_modules[index]) {
+        Some(column) if !matches!(column.sort, ColumnSort::Key(_)) => {
+            let title = column.title.as_deref().unwrap_or_default();
+            let direction = if state.sort.reverse { ", reversed" } else { "" };
+            format!("sort: {}{}", title, direction)
+        }
+        None if state.hooks.sorter.is_some() => "sort: custom".to_string(),
+        _ => format!("sort: {}", state.sort.label()),
+    }
+}
+
+/// Permissions and size of the selected entry.
+fn selected_info(state: &AppState) -> String {
+    let Some(meta) = state.entries.get(state.selected).and_then(|path| std::fs::symlink_metadata(path).ok()) else {
+        return String::new();
+    };
+    let file_type = if meta.is_dir() {
+        'd'
+    } else if meta.is_symlink() {
+        'l'
+    } else {
+        '-'
+    };
+    let mode = meta.permissions().mode();
+    let mut permissions = String::from(file_type);
+    for (bit, c) in (0..9).rev().zip("rwxrwxrwx".chars()) {
+        permissions.push(if mode & (1 << bit) != 0 { c } else { '-' });
+    }
+    if meta.is_dir() {
+        permissions
+    } else {
+        format!("{} {}", permissions, human_size(meta.len()))
+    }
+}
+
+fn filter(state: &AppState) -> String {
+    let Some(filter) = &state.filter else {
+        return String::new();
+    };
+    let mode = match filter.mode() {
+        MatchMode::Fuzzy => "",
+        MatchMode::Text => " (text)",
+        MatchMode::Regex => " (regex)",
+    };
+    format!("Filter{}: {}", mode, filter.query())
+}
+
+/// The branch, with the commits it is ahead (`↑`) or behind (`↓`).
+fn git_branch(state: &AppState) -> String {
+    let Some(branch) = state.git_branch() else {
+        return String::new();
+    };
+    let mut text = branch.name.clone();
+    if branch.ahead > 0 {
+        text.push_str(&format!(" ↑{}", branch.ahead));
+    }
+    if branch.behind > 0 {
+        text.push_str(&format!(" ↓{}", branch.behind));
+    }
+    text
+}
+
+fn hidden(state: &AppState) -> String {
+    let mut hiding = Vec::new();
+    if !state.visibility.show_hidden {
+        hiding.push("dotfiles");
+    }
+    if state.visibility.hide_ignored {
+        hiding.push("ignored");
+    }
+    if hiding.is_empty() {
+        return String::new();
+    }
+    format!("hiding: {}", hiding.join(", "))
+}
+
+/// Space available to unprivileged users on the filesystem holding `path`.
+fn free_space(path: &Path) -> Option<u64> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stats` is a valid statvfs to fill
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+    Some(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+/// Formats a size in bytes with one decimal, e.g. `1.5 MB`.
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KB", "MB", "GB", "TB", "PB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}